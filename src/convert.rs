@@ -0,0 +1,214 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::format_error::FormatError;
+use crate::lrc;
+use crate::midi::{self, Melody};
+use crate::musicxml::{self, Part};
+use crate::song::Song;
+use crate::song_library::SongLibrary;
+use crate::track::Track;
+use crate::track_file::TrackError;
+use crate::ultrastar;
+
+/// Tempo of exported MIDI files when neither the track nor the command line
+/// gives one.
+const DEFAULT_BPM: f32 = 120.0;
+
+#[derive(Debug)]
+pub enum CommandError {
+    /// The command line is wrong, for the reason given.
+    Usage(String),
+    Format(FormatError),
+    Track(TrackError),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::Usage(reason) => write!(f, "{}", reason),
+            CommandError::Format(e) => e.fmt(f),
+            CommandError::Track(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CommandError::Usage(_) => None,
+            CommandError::Format(e) => Some(e),
+            CommandError::Track(e) => Some(e),
+        }
+    }
+}
+
+impl From<FormatError> for CommandError {
+    fn from(e: FormatError) -> Self {
+        CommandError::Format(e)
+    }
+}
+
+impl From<TrackError> for CommandError {
+    fn from(e: TrackError) -> Self {
+        CommandError::Track(e)
+    }
+}
+
+fn usage(reason: impl Into<String>) -> CommandError {
+    CommandError::Usage(reason.into())
+}
+
+/// Command line arguments split into `--name value` options and the rest.
+struct Args<'a> {
+    options: Vec<(&'a str, &'a str)>,
+    positional: Vec<&'a str>,
+}
+
+impl<'a> Args<'a> {
+    /// Splits `args`, allowing only the options named in `options`.
+    fn parse(args: &[&'a str], options: &[&str]) -> Result<Self, CommandError> {
+        let mut parsed = Args {
+            options: vec![],
+            positional: vec![],
+        };
+        let mut args = args.iter();
+        while let Some(&arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(name) if options.contains(&name) => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| usage(format!("{} needs a value", arg)))?;
+                    parsed.options.push((name, value));
+                }
+                Some(_) => return Err(usage(format!("unknown option {}", arg))),
+                None => parsed.positional.push(arg),
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// The value of an option. The last one wins if it is given twice.
+    fn get(&self, name: &str) -> Option<&'a str> {
+        self.options
+            .iter()
+            .rev()
+            .find(|(option, _)| *option == name)
+            .map(|(_, value)| *value)
+    }
+
+    fn parsed<T: FromStr>(&self, name: &str) -> Result<Option<T>, CommandError> {
+        self.get(name)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| usage(format!("bad --{} {:?}", name, value)))
+            })
+            .transpose()
+    }
+
+    /// The input and output paths.
+    fn paths(&self) -> Result<(&'a Path, &'a Path), CommandError> {
+        match self.positional[..] {
+            [input, output] => Ok((Path::new(input), Path::new(output))),
+            _ => Err(usage("expected an input and an output path")),
+        }
+    }
+}
+
+/// Runs `karaoke import`, which turns the melody of a file in another format
+/// into a `.track` file.
+pub fn import(args: &[&str]) -> Result<(), CommandError> {
+    let args = Args::parse(args, &["track", "channel", "part", "voice", "phrase-break"])?;
+    let (input, output) = args.paths()?;
+    let phrase_break: Option<u32> = args.parsed("phrase-break")?;
+    let extension = input
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let track = match extension.as_str() {
+        "mid" | "midi" => {
+            let melody = match (args.parsed("track")?, args.parsed("channel")?) {
+                (Some(_), Some(_)) => {
+                    return Err(usage("--track and --channel can't both be given"))
+                }
+                (Some(track), None) => Melody::Track(track),
+                (None, channel) => Melody::Channel(channel.unwrap_or(0)),
+            };
+            let mut options = midi::ImportOptions::new(melody);
+            options.phrase_break = phrase_break.unwrap_or(options.phrase_break);
+            midi::read(input, options)?
+        }
+        "musicxml" | "mxl" | "xml" => {
+            // A part is picked by position if the value is a number, and by
+            // id or name otherwise.
+            let part = match args.get("part") {
+                Some(part) => part
+                    .parse()
+                    .map(Part::Index)
+                    .unwrap_or_else(|_| Part::Name(part.to_string())),
+                None => Part::Index(0),
+            };
+            let mut options = musicxml::ImportOptions::new(part);
+            options.voice = args.get("voice").map(|voice| voice.to_string());
+            options.phrase_break = phrase_break.unwrap_or(options.phrase_break);
+            musicxml::read(input, &options)?
+        }
+        _ => return Err(usage(format!("can't import {}", input.display()))),
+    };
+    track.write(output)?;
+    Ok(())
+}
+
+/// Runs `karaoke export`, which writes a song folder or a `.track` file in
+/// another format.
+pub fn export(args: &[&str]) -> Result<(), CommandError> {
+    let args = Args::parse(args, &["format", "bpm"])?;
+    let (input, output) = args.paths()?;
+    let bpm: Option<f32> = args.parsed("bpm")?;
+    let format = args
+        .get("format")
+        .ok_or_else(|| usage("--format is needed"))?;
+    let song = read_song(input)?;
+    match format {
+        "ultrastar" => {
+            let bpm = bpm
+                .or_else(|| tracks(&song).iter().find_map(|track| track.header.bpm))
+                .ok_or_else(|| usage("the song has no BPM of its own, so --bpm is needed"))?;
+            ultrastar::write(&song, bpm, output)?;
+        }
+        "lrc" => lrc::write(lead_track(&song)?, output)?,
+        "midi" => {
+            let track = lead_track(&song)?;
+            let bpm = bpm.or(track.header.bpm).unwrap_or(DEFAULT_BPM);
+            midi::write(track, midi::ExportOptions::new(bpm), output)?;
+        }
+        _ => return Err(usage(format!("unknown format {}", format))),
+    }
+    Ok(())
+}
+
+/// Reads a song folder, or a single `.track` file as a song of its own.
+fn read_song(path: &Path) -> Result<Song, CommandError> {
+    if path.is_dir() {
+        return SongLibrary::read_song(path).map_err(|e| FormatError::io(path, e).into());
+    }
+    let track = Track::read(path)?;
+    let mut song = Song::new(track.name.clone(), String::new(), String::new(), None, None);
+    song.add_track(track.name.clone(), track);
+    Ok(song)
+}
+
+/// The track to export when a format holds only one.
+fn lead_track(song: &Song) -> Result<&Track, CommandError> {
+    song.lead_track()
+        .ok_or_else(|| usage("the song has no tracks"))
+}
+
+/// The song's tracks in name order.
+fn tracks(song: &Song) -> Vec<&Track> {
+    let mut tracks: Vec<&Track> = song.tracks.values().collect();
+    tracks.sort_by(|a, b| a.name.cmp(&b.name));
+    tracks
+}
@@ -0,0 +1,61 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Error produced while importing or exporting a foreign song format.
+#[derive(Debug)]
+pub enum FormatError {
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        /// 1-based line number, or 0 when the problem isn't tied to a line.
+        line: usize,
+        reason: String,
+    },
+}
+
+impl FormatError {
+    pub fn io(path: &Path, source: std::io::Error) -> Self {
+        FormatError::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    pub fn parse(path: &Path, line: usize, reason: &str) -> Self {
+        FormatError::Parse {
+            path: path.to_path_buf(),
+            line,
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            FormatError::Parse {
+                path,
+                line: 0,
+                reason,
+            } => {
+                write!(f, "{}: {}", path.display(), reason)
+            }
+            FormatError::Parse { path, line, reason } => {
+                write!(f, "{}:{}: {}", path.display(), line, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FormatError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FormatError::Io { source, .. } => Some(source),
+            FormatError::Parse { .. } => None,
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use std::io::Read;
 use std::{
-    path::{Path, PathBuf},
+    path::Path,
     process::{ChildStdout, Command, Stdio},
 };
 
@@ -8,7 +8,6 @@ use crate::timer::Timer;
 
 #[derive(Debug)]
 pub struct FrameSplitter {
-    path: PathBuf,
     raw_data_handle: ChildStdout,
     frame_index: usize,
     last_frame: Option<Vec<u8>>,
@@ -23,7 +22,6 @@ impl FrameSplitter {
         let fps = Self::read_fps(path)?;
         let raw_data_handle = Self::initialize_pipe(path)?;
         Ok(Self {
-            path: path.to_path_buf(),
             raw_data_handle,
             frame_index: 0,
             last_frame: None,
@@ -38,12 +36,14 @@ impl FrameSplitter {
         let needed_frame = timer.elapsed_time().as_millis() as f64;
         let needed_frame = needed_frame / 1000.0;
         let needed_frame = needed_frame * self.fps.0 as f64 / self.fps.1 as f64;
-        if self.last_frame == None || needed_frame > self.frame_index as f64 {
+        if self.last_frame.is_none() || needed_frame > self.frame_index as f64 {
             // Pull frame from video
             let frame_size = 4 * self.width * self.height;
             let mut frame = vec![0u8; frame_size];
-            self.raw_data_handle.read_exact(&mut frame);
-            self.last_frame = Some(frame);
+            // Once the video runs out, its last frame stays up.
+            if self.raw_data_handle.read_exact(&mut frame).is_ok() || self.last_frame.is_none() {
+                self.last_frame = Some(frame);
+            }
             self.frame_index += 1;
         }
 
@@ -0,0 +1,99 @@
+/// Most snapshots kept for undoing. The oldest are dropped first.
+const LIMIT: usize = 500;
+
+/// Undo and redo stacks of snapshots taken before each edit.
+///
+/// Edits of the same kind in a row, such as holding down a key, are undone
+/// together. Any other action in between starts a new step; call `seal` for
+/// actions that aren't edits, like moving the selection.
+pub struct History<T> {
+    undo: Vec<(T, &'static str)>,
+    redo: Vec<(T, &'static str)>,
+    /// Whether the newest undo step can still take more edits of its kind.
+    open: bool,
+}
+
+impl<T: Clone> History<T> {
+    pub fn new() -> Self {
+        History {
+            undo: vec![],
+            redo: vec![],
+            open: false,
+        }
+    }
+
+    /// Records `state` before an edit of the given kind is applied to it.
+    pub fn record(&mut self, state: &T, kind: &'static str) {
+        self.redo.clear();
+        if self.open && self.undo.last().map(|(_, last)| *last) == Some(kind) {
+            return;
+        }
+        if self.undo.len() == LIMIT {
+            self.undo.remove(0);
+        }
+        self.undo.push((state.clone(), kind));
+        self.open = true;
+    }
+
+    /// Ends the current undo step, so the next edit starts a new one.
+    pub fn seal(&mut self) {
+        self.open = false;
+    }
+
+    /// Restores the state before the last step. Returns the kind of edit
+    /// undone, or `None` if there was nothing to undo.
+    pub fn undo(&mut self, state: &mut T) -> Option<&'static str> {
+        let (previous, kind) = self.undo.pop()?;
+        self.redo.push((std::mem::replace(state, previous), kind));
+        self.open = false;
+        Some(kind)
+    }
+
+    /// Reapplies the last undone step. Returns the kind of edit redone, or
+    /// `None` if there was nothing to redo.
+    pub fn redo(&mut self, state: &mut T) -> Option<&'static str> {
+        let (next, kind) = self.redo.pop()?;
+        self.undo.push((std::mem::replace(state, next), kind));
+        self.open = false;
+        Some(kind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coalesces_repeated_edits() {
+        let mut history = History::new();
+        let mut state = 0;
+        for _ in 0..3 {
+            history.record(&state, "raise");
+            state += 1;
+        }
+        history.seal();
+        history.record(&state, "raise");
+        state += 1;
+
+        assert_eq!(history.undo(&mut state), Some("raise"));
+        assert_eq!(state, 3);
+        assert_eq!(history.undo(&mut state), Some("raise"));
+        assert_eq!(state, 0);
+        assert_eq!(history.undo(&mut state), None);
+        assert_eq!(history.redo(&mut state), Some("raise"));
+        assert_eq!(state, 3);
+    }
+
+    #[test]
+    fn new_edit_clears_redo() {
+        let mut history = History::new();
+        let mut state = 0;
+        history.record(&state, "raise");
+        state += 1;
+        history.undo(&mut state);
+        history.record(&state, "lower");
+        state -= 1;
+        assert_eq!(history.redo(&mut state), None);
+        assert_eq!(state, -1);
+    }
+}
@@ -0,0 +1,313 @@
+use std::fs;
+use std::path::Path;
+
+use crate::format_error::FormatError;
+use crate::note::{self, Note, NoteKind};
+use crate::track::{Phrase, Track};
+use crate::track_file::{Resolution, TrackHeader, FORMAT_VERSION};
+
+/// Timed lyrics carry no melody, so their notes get this placeholder pitch
+/// and are freestyle so they aren't scored.
+pub const LYRIC_PITCH: i8 = 60;
+
+/// How long the very last word lasts when nothing marks its end.
+const LAST_WORD_LENGTH: u32 = 1000;
+
+/// One timed line of lyrics. `words` holds (start, text) in milliseconds.
+struct Line {
+    time: u32,
+    words: Vec<(u32, String)>,
+    end: Option<u32>,
+}
+
+/// Reads an LRC or enhanced LRC file into a track with one phrase per line.
+pub fn read(path: &Path) -> Result<Track, FormatError> {
+    let bytes = fs::read(path).map_err(|e| FormatError::io(path, e))?;
+    parse(&String::from_utf8_lossy(&bytes), path)
+}
+
+/// Parses LRC lyrics.
+///
+/// Every `<mm:ss.xx>` word timestamp of an enhanced LRC line starts a new
+/// freestyle note, and a plain line is a single note. Words last until the
+/// next word, a closing timestamp with no text after it, or the next line.
+/// Time until the next line's first word becomes a rest at the end of the
+/// phrase.
+pub fn parse(s: &str, path: &Path) -> Result<Track, FormatError> {
+    let mut title = None;
+    let mut offset: i64 = 0;
+    let mut length = None;
+    let mut lines = vec![];
+
+    for (i, text) in s.lines().enumerate() {
+        let error = |reason: &str| FormatError::parse(path, i + 1, reason);
+        let mut rest = text.trim_start_matches('\u{feff}').trim();
+        let mut times = vec![];
+        while let Some(tag) = rest.strip_prefix('[') {
+            let end = tag.find(']').ok_or_else(|| error("unclosed ["))?;
+            let (tag, after) = (&tag[..end], &tag[end + 1..]);
+            rest = after;
+            if let Some(time) = parse_time(tag) {
+                times.push(time);
+                continue;
+            }
+            match tag.split_once(':') {
+                Some(("ti", value)) => title = Some(value.trim().to_string()),
+                Some(("offset", value)) => {
+                    offset = value.trim().parse().map_err(|_| error("bad offset"))?
+                }
+                Some(("length", value)) => length = parse_time(value.trim()),
+                _ => (),
+            }
+        }
+        if times.is_empty() {
+            continue;
+        }
+        let (words, end) = parse_words(rest).ok_or_else(|| error("bad word timestamp"))?;
+        // A line with several timestamps is repeated, with its word times
+        // relative to the first one.
+        for &time in &times {
+            let shift = |t: u32| (t as i64 - times[0] as i64 + time as i64 - offset).max(0) as u32;
+            let words = words
+                .iter()
+                .map(|(start, text)| (shift(start.unwrap_or(times[0])), text.clone()))
+                .collect();
+            lines.push(Line {
+                time: shift(times[0]),
+                words,
+                end: end.map(shift),
+            });
+        }
+    }
+    lines.sort_by_key(|line| line.time);
+
+    let mut phrases = vec![];
+    for (i, line) in lines.iter().enumerate() {
+        let next_line = lines.get(i + 1).map(|next| next.time);
+        let line_end = line.end.or(next_line).or(length).unwrap_or_else(|| {
+            line.words
+                .last()
+                .map_or(line.time, |(start, _)| start + LAST_WORD_LENGTH)
+        });
+        let mut phrase: Phrase = vec![];
+        for (w, (start, text)) in line.words.iter().enumerate() {
+            let end = line.words.get(w + 1).map_or(line_end, |(next, _)| *next);
+            let length = end.saturating_sub(*start);
+            phrase.push(
+                Note::new(length, LYRIC_PITCH, true, text.clone()).with_kind(NoteKind::Freestyle),
+            );
+        }
+        if phrase.is_empty() {
+            continue;
+        }
+        note::mark_word_boundaries(&mut phrase);
+        let next_word = lines[i + 1..]
+            .iter()
+            .find_map(|next| next.words.first().map(|(start, _)| *start));
+        if let Some(next_word) = next_word {
+            let rest = next_word.saturating_sub(line_end);
+            if rest > 0 {
+                phrase.push(Note::new(rest, LYRIC_PITCH, false, "".to_string()));
+            }
+        }
+        phrases.push(phrase);
+    }
+
+    let first_word = lines.iter().find_map(|line| line.words.first());
+    let gap = match first_word {
+        Some((start, _)) => *start,
+        None => return Err(FormatError::parse(path, 0, "no timed lyrics")),
+    };
+    let mut track = Track::new();
+    track.name = title.unwrap_or_else(|| {
+        path.file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default()
+    });
+    track.header = TrackHeader {
+        version: FORMAT_VERSION,
+        bpm: None,
+        gap,
+        resolution: Resolution::Milliseconds,
+        ..TrackHeader::new()
+    };
+    track.phrases = phrases;
+    Ok(track)
+}
+
+/// Words of a line as (start, text). Text before the first word timestamp
+/// has no start of its own and begins with the line.
+type Words = Vec<(Option<u32>, String)>;
+
+/// Splits the text of a line into words at `<mm:ss.xx>` timestamps. A
+/// timestamp with no text after it ends the line.
+fn parse_words(s: &str) -> Option<(Words, Option<u32>)> {
+    let mut words = vec![];
+    let mut end = None;
+    let (before, mut rest) = match s.find('<') {
+        Some(i) => (&s[..i], &s[i..]),
+        None => (s, ""),
+    };
+    if !before.trim().is_empty() {
+        words.push((None, before.to_string()));
+    }
+    while let Some(stamp) = rest.strip_prefix('<') {
+        let close = stamp.find('>')?;
+        let time = parse_time(&stamp[..close])?;
+        let after = &stamp[close + 1..];
+        let next = after.find('<').unwrap_or(after.len());
+        let text = &after[..next];
+        if text.trim().is_empty() {
+            end = Some(time);
+        } else {
+            words.push((Some(time), text.to_string()));
+            end = None;
+        }
+        rest = &after[next..];
+    }
+    Some((words, end))
+}
+
+/// Parses `mm:ss`, `mm:ss.xx` or `mm:ss.xxx` into milliseconds.
+fn parse_time(s: &str) -> Option<u32> {
+    let (minutes, seconds) = s.split_once(':')?;
+    let minutes = minutes.parse::<u32>().ok()?;
+    let (seconds, fraction) = match seconds.split_once(['.', ':']) {
+        Some((seconds, fraction)) => (seconds, fraction),
+        None => (seconds, ""),
+    };
+    let seconds = seconds.parse::<u32>().ok()?;
+    if seconds >= 60 || fraction.len() > 3 || !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let millis = match fraction.len() {
+        0 => 0,
+        n => fraction.parse::<u32>().ok()? * 10u32.pow(3 - n as u32),
+    };
+    Some((minutes * 60 + seconds) * 1000 + millis)
+}
+
+/// Writes `track` as enhanced LRC.
+pub fn write(track: &Track, path: &Path) -> Result<(), FormatError> {
+    fs::write(path, serialize(track)).map_err(|e| FormatError::io(path, e))
+}
+
+/// Serializes `track` as enhanced LRC with one line per phrase. Each line
+/// starts at its first lyric and ends with a timestamp for the end of its last
+/// lyric. Notes without lyrics only move time forward.
+pub fn serialize(track: &Track) -> String {
+    let time =
+        |position: u32| track.header.gap + track.header.position_to_ms(position).round() as u32;
+    let mut s = format!("[ti:{}]\n", track.name);
+    let mut position = 0;
+    for phrase in &track.phrases {
+        let mut line = String::new();
+        let mut line_end = None;
+        for (i, note) in phrase.iter().enumerate() {
+            let lyric = note.lyric.replace(['\n', '\r'], " ");
+            if !lyric.trim().is_empty() {
+                if line_end.is_none() {
+                    line += &format!("[{}]", format_time(time(position)));
+                }
+                line += &format!(
+                    "<{}>{}{}",
+                    format_time(time(position)),
+                    lyric.trim(),
+                    note::lyric_separator(&phrase[i..])
+                );
+                line_end = Some(time(position + note.length));
+            }
+            position += note.length;
+        }
+        if let Some(end) = line_end {
+            s += &format!("{}<{}>\n", line, format_time(end));
+        }
+    }
+    s
+}
+
+fn format_time(ms: u32) -> String {
+    let centis = (ms + 5) / 10;
+    format!(
+        "{:02}:{:02}.{:02}",
+        centis / 6000,
+        centis / 100 % 60,
+        centis % 100
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_lrc(s: &str) -> Track {
+        parse(s, Path::new("song.lrc")).unwrap()
+    }
+
+    /// (length, lyric) of each note, with rests as `None`.
+    fn notes(track: &Track) -> Vec<Vec<(u32, Option<&str>)>> {
+        track
+            .phrases
+            .iter()
+            .map(|phrase| {
+                phrase
+                    .iter()
+                    .map(|note| (note.length, note.voiced.then_some(note.lyric.as_str())))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn words_are_freestyle_notes() {
+        let track = parse_lrc("[00:01.00]<00:01.00>Hel<00:01.50>lo <00:02.00>\n[00:04.00]World\n");
+        assert_eq!(
+            notes(&track),
+            [
+                vec![(500, Some("Hel")), (500, Some("lo")), (2000, None)],
+                vec![(LAST_WORD_LENGTH, Some("World"))],
+            ]
+        );
+        let words: Vec<&Note> = track
+            .phrases
+            .iter()
+            .flatten()
+            .filter(|note| note.voiced)
+            .collect();
+        assert!(words.iter().all(|note| note.kind == NoteKind::Freestyle));
+        assert!(words[1].continues);
+        assert_eq!(track.header.gap, 1000);
+    }
+
+    #[test]
+    fn applies_offset() {
+        let track = parse_lrc("[offset:+500]\n[00:01.00]Hello\n[00:03.00]World\n");
+        assert_eq!(track.header.gap, 500);
+        assert_eq!(notes(&track)[0], [(2000, Some("Hello"))]);
+    }
+
+    #[test]
+    fn repeats_lines_with_several_timestamps() {
+        let track =
+            parse_lrc("[ti:Song]\n[00:01.00][00:05.00]La <00:01.50>la<00:02.00>\n[00:03.00]Di\n");
+        assert_eq!(track.name, "Song");
+        assert_eq!(
+            notes(&track),
+            [
+                vec![(500, Some("La")), (500, Some("la")), (1000, None)],
+                vec![(2000, Some("Di"))],
+                vec![(500, Some("La")), (500, Some("la"))],
+            ]
+        );
+    }
+
+    #[test]
+    fn round_trips_enhanced_lyrics() {
+        let lrc = "[ti:Song]\n\
+            [00:01.00]<00:01.00>Hel<00:01.50>lo <00:02.00>world<00:02.50>\n\
+            [00:04.00]<00:04.00>Again<00:05.00>\n";
+        let track = parse_lrc(lrc);
+        assert_eq!(serialize(&track), lrc);
+        assert_eq!(parse_lrc(&serialize(&track)).phrases, track.phrases);
+    }
+}
@@ -1,15 +1,31 @@
+mod convert;
+mod format_error;
 mod frame_splitter;
+mod history;
+mod lrc;
+mod manifest;
 mod mic;
+mod midi;
+mod musicxml;
 mod note;
+mod preview;
+mod recorder;
 mod song;
 mod song_library;
 mod song_panel;
 mod song_view;
+mod syllables;
 mod timer;
 mod track;
+mod track_file;
+mod ultrastar;
 
 use eframe::egui;
+use std::path::Path;
+use std::process;
 
+use crate::convert::CommandError;
+use crate::note::PitchMatch;
 use crate::song_library::SongLibrary;
 use crate::song_panel::TrackSession;
 
@@ -17,6 +33,8 @@ struct Karaoke {
     state: KaraokeState,
     library: SongLibrary,
     session: Option<TrackSession>,
+    /// How sung pitches are scored in every session.
+    pitch_match: PitchMatch,
     scroll_position: f32, // This is used to calculate the scrollbar
                           // offset. It approaches library.selection_index
                           // every tick.
@@ -27,7 +45,6 @@ enum KaraokeState {
     Library,
     SongSelection,
     Playing,
-    Paused,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -38,15 +55,11 @@ enum Message {
     FocusLeft,
     Focus(usize),
     SelectFocused,
-    Play,
-    Pause,
-    Resume,
     Tick,
-    None,
 }
 
 impl Karaoke {
-    fn new(cc: &eframe::CreationContext<'_>) -> Self {
+    fn new(cc: &eframe::CreationContext<'_>, pitch_match: PitchMatch) -> Self {
         let mut library = SongLibrary::new(std::path::Path::new("songs"));
         for song in library.songs.iter_mut() {
             if let Some(cover) = &mut song.album_cover {
@@ -57,39 +70,32 @@ impl Karaoke {
             state: KaraokeState::Library,
             library,
             session: None,
+            pitch_match,
             scroll_position: 0.0,
         }
     }
 
-    fn title(&self) -> String {
-        String::from("Karaoke")
-    }
-
     fn handle_message(&mut self, message: Message) {
         match message {
             Message::FocusUp => match self.state {
                 KaraokeState::Library => self.library.select_previous(),
                 KaraokeState::SongSelection => (),
                 KaraokeState::Playing => (),
-                KaraokeState::Paused => (),
             },
             Message::FocusDown => match self.state {
                 KaraokeState::Library => self.library.select_next(),
                 KaraokeState::SongSelection => (),
                 KaraokeState::Playing => (),
-                KaraokeState::Paused => (),
             },
             Message::FocusRight => match self.state {
                 KaraokeState::Library => self.state = KaraokeState::SongSelection,
                 KaraokeState::SongSelection => (),
                 KaraokeState::Playing => (),
-                KaraokeState::Paused => (),
             },
             Message::FocusLeft => match self.state {
                 KaraokeState::Library => (),
                 KaraokeState::SongSelection => self.state = KaraokeState::Library,
                 KaraokeState::Playing => (),
-                KaraokeState::Paused => (),
             },
             Message::Focus(i) => self.library.select(i),
             Message::SelectFocused => match self.state {
@@ -102,21 +108,17 @@ impl Karaoke {
                         .songs
                         .get(self.library.selection_index)
                         .unwrap();
-                    self.session = if let Ok(session) = TrackSession::new(song.clone()) {
-                        Some(session)
-                    } else {
-                        //TODO handle not being able to load song
-                        None
+                    self.session = match TrackSession::new(song.clone(), self.pitch_match) {
+                        Ok(session) => Some(session),
+                        Err(e) => {
+                            eprintln!("Couldn't play {}: {}", song.name, e);
+                            None
+                        }
                     }
                 }
                 KaraokeState::Playing => (),
-                KaraokeState::Paused => (),
             },
-            Message::Play => (),
-            Message::Pause => (),
-            Message::Resume => (),
             Message::Tick => self.tick(),
-            Message::None => (),
         }
     }
 
@@ -128,20 +130,17 @@ impl Karaoke {
             KaraokeState::SongSelection => {
                 self.update_scroll_position();
             }
-            KaraokeState::Playing => match &mut self.session {
-                Some(session) => {
+            KaraokeState::Playing => {
+                if let Some(session) = &mut self.session {
                     session.tick();
                     match session.state {
                         song_panel::State::Playing => (),
-                        song_panel::State::Paused => (),
                         song_panel::State::Finished => {
                             self.state = KaraokeState::Library;
                         }
                     };
                 }
-                None => (),
-            },
-            KaraokeState::Paused => todo!(),
+            }
         }
     }
 
@@ -185,6 +184,9 @@ impl eframe::App for Karaoke {
                                                 song.artist.clone(),
                                                 song.album.clone(),
                                             ]
+                                            .into_iter()
+                                            .filter(|line| !line.is_empty())
+                                            .collect::<Vec<_>>()
                                             .join("\n");
                                             if let Some(cover) = &song.album_cover {
                                                 button = egui::Button::image_and_text(
@@ -261,9 +263,6 @@ impl eframe::App for Karaoke {
                         None => ui.label("Unable to play"),
                     });
                 }),
-                KaraokeState::Paused => egui::Frame::none().show(ui, |ui| {
-                    ui.label("Paused");
-                }),
             }
         });
         ctx.request_repaint();
@@ -271,7 +270,7 @@ impl eframe::App for Karaoke {
 }
 
 fn convert_scroll_position(scroll_position: f32, item_size: f32) -> f32 {
-    (scroll_position + 0.5) * item_size as f32
+    (scroll_position + 0.5) * item_size
 }
 
 fn handle_input(karaoke: &mut Karaoke, ctx: &egui::Context) {
@@ -293,16 +292,53 @@ fn handle_input(karaoke: &mut Karaoke, ctx: &egui::Context) {
 }
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        [] => play(PitchMatch::Exact),
+        ["--any-octave"] => play(PitchMatch::AnyOctave),
+        ["edit", path] => {
+            if let Err(e) = song_view::edit(Path::new(path)) {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        }
+        ["import", ref args @ ..] => {
+            if let Err(e) = convert::import(args) {
+                fail(e);
+            }
+        }
+        ["export", ref args @ ..] => {
+            if let Err(e) = convert::export(args) {
+                fail(e);
+            }
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    }
+}
+
+const USAGE: &str = "usage: karaoke [--any-octave]
+       karaoke edit <track file>
+       karaoke import [--track <n> | --channel <0-15>] [--phrase-break <ms>] <midi file> <track file>
+       karaoke import [--part <n or name>] [--voice <voice>] [--phrase-break <ms>] <musicxml file> <track file>
+       karaoke export --format <ultrastar|midi|lrc> [--bpm <bpm>] <song folder or track file> <output file>";
+
+fn fail(e: CommandError) -> ! {
+    eprintln!("{}", e);
+    if let CommandError::Usage(_) = e {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+    process::exit(1);
+}
+
+fn play(pitch_match: PitchMatch) {
     let native_options = eframe::NativeOptions::default();
     eframe::run_native(
         "Karaoke",
         native_options,
-        Box::new(|cc| Box::new(Karaoke::new(cc))),
+        Box::new(move |cc| Box::new(Karaoke::new(cc, pitch_match))),
     )
-
-    //let song = Song::read(Path::new("test.song"));
-    //let mut siv = cursive::default();
-    //siv.add_global_callback('q', |s| s.quit());
-    //siv.add_layer(views::ScrollView::new(SongView::new(song).with_name("view")).scroll_x(true));
-    //siv.run();
 }
@@ -0,0 +1,195 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::format_error::FormatError;
+use crate::song::{self, Role, Song};
+
+/// Name of the manifest file in a song folder.
+pub const FILE_NAME: &str = "song.toml";
+
+/// Song metadata read from `song.toml`. Every field is optional. File names
+/// are relative to the song folder.
+///
+/// ```toml
+/// title = "Song"
+/// artist = "Band"
+/// album = "Record"
+/// year = 1999
+/// cover = "cover.png"
+/// preview_start = 45000
+///
+/// [tracks.Lead]
+/// role = "lead"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Manifest {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub year: Option<u32>,
+    pub genre: Option<String>,
+    pub language: Option<String>,
+    pub cover: Option<PathBuf>,
+    pub video: Option<PathBuf>,
+    pub audio: Option<PathBuf>,
+    /// Where the library preview starts, in milliseconds.
+    pub preview_start: Option<u32>,
+    /// Settings for each track, by track name.
+    pub tracks: HashMap<String, TrackEntry>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrackEntry {
+    pub role: Option<Role>,
+}
+
+impl Manifest {
+    /// Reads `song.toml` from a song folder, or returns `None` if the folder
+    /// has no manifest.
+    pub fn read_dir(dir: &Path) -> Result<Option<Self>, FormatError> {
+        let path = dir.join(FILE_NAME);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let s = fs::read_to_string(&path).map_err(|e| FormatError::io(&path, e))?;
+        Self::parse(&s, &path).map(Some)
+    }
+
+    pub fn parse(s: &str, path: &Path) -> Result<Self, FormatError> {
+        // toml's errors already say where in the file they are.
+        toml::from_str(s).map_err(|e| FormatError::parse(path, 0, &e.to_string()))
+    }
+
+    /// Fills the manifest's fields into `song`, replacing what was guessed
+    /// from the files in `dir`.
+    pub fn apply(self, song: &mut Song, dir: &Path) {
+        if let Some(title) = self.title {
+            song.name = title;
+        }
+        if let Some(artist) = self.artist {
+            song.artist = artist;
+        }
+        if let Some(album) = self.album {
+            song.album = album;
+        }
+        song.year = self.year.or(song.year);
+        song.genre = self.genre.or(song.genre.take());
+        song.language = self.language.or(song.language.take());
+        song.preview_start = self.preview_start.or(song.preview_start);
+        if let Some(cover) = self.cover {
+            let mut image = song::Image::new();
+            image.load_image(&dir.join(cover));
+            song.album_cover = Some(image);
+        }
+        if let Some(video) = self.video {
+            song.video_path = Some(dir.join(video));
+        }
+        if let Some(audio) = self.audio {
+            song.audio_path = Some(dir.join(audio));
+        }
+        for (name, entry) in self.tracks {
+            if !song.tracks.contains_key(&name) {
+                eprintln!("{} names a track that doesn't exist: {}", FILE_NAME, name);
+                continue;
+            }
+            if let Some(role) = entry.role {
+                song.roles.insert(name, role);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::track::Track;
+
+    fn parse(s: &str) -> Result<Manifest, FormatError> {
+        Manifest::parse(s, Path::new(FILE_NAME))
+    }
+
+    #[test]
+    fn reads_manifests() {
+        let manifest = parse(
+            r#"
+            title = "Song"
+            artist = "Band"
+            year = 1999
+            audio = "song.ogg"
+            preview_start = 45000
+
+            [tracks.Lead]
+            role = "lead"
+
+            [tracks.Choir]
+            role = "backing"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(manifest.title.as_deref(), Some("Song"));
+        assert_eq!(manifest.artist.as_deref(), Some("Band"));
+        assert_eq!(manifest.year, Some(1999));
+        assert_eq!(manifest.audio, Some(PathBuf::from("song.ogg")));
+        assert_eq!(manifest.preview_start, Some(45000));
+        assert_eq!(manifest.tracks["Lead"].role, Some(Role::Lead));
+        assert_eq!(manifest.tracks["Choir"].role, Some(Role::Backing));
+    }
+
+    #[test]
+    fn leaves_out_missing_fields() {
+        let manifest = parse("title = \"Song\"\n[tracks.Lead]\n").unwrap();
+        assert_eq!(manifest.artist, None);
+        assert_eq!(manifest.cover, None);
+        assert_eq!(manifest.preview_start, None);
+        assert_eq!(manifest.tracks["Lead"].role, None);
+        assert!(parse("").unwrap().tracks.is_empty());
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        assert!(parse("titel = \"Song\"\n").is_err());
+        assert!(parse("[tracks.Lead]\nrole = \"lead\"\nkey = 3\n").is_err());
+        assert!(parse("[tracks.Lead]\nrole = \"soloist\"\n").is_err());
+    }
+
+    #[test]
+    fn applies_to_songs() {
+        let mut song = Song::new(
+            "guess".to_string(),
+            "guess".to_string(),
+            String::new(),
+            None,
+            None,
+        );
+        song.genre = Some("Rock".to_string());
+        for name in ["Alto", "Lead"] {
+            let mut track = Track::new();
+            track.name = name.to_string();
+            song.add_track(name.to_string(), track);
+        }
+        let manifest = parse(
+            r#"
+            title = "Song"
+            audio = "song.ogg"
+
+            [tracks.Lead]
+            role = "lead"
+
+            [tracks.Missing]
+            role = "harmony"
+            "#,
+        )
+        .unwrap();
+        manifest.apply(&mut song, Path::new("songs/song"));
+        assert_eq!(song.name, "Song");
+        assert_eq!(song.artist, "guess");
+        assert_eq!(song.genre.as_deref(), Some("Rock"));
+        assert_eq!(song.audio_path, Some(PathBuf::from("songs/song/song.ogg")));
+        assert_eq!(song.roles.len(), 1);
+        assert_eq!(song.lead_track().unwrap().name, "Lead");
+    }
+}
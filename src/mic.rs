@@ -5,10 +5,13 @@ use ringbuf::RingBuffer;
 use rustfft::{num_complex::Complex, FftPlanner};
 use std::time;
 
-use crate::note::Note;
+use crate::note::{self, Note};
+
+/// Windows quieter than this, as the RMS of their samples, are silence.
+const SILENCE_LEVEL: f32 = 0.01;
 
 pub struct Microphone {
-    device: cpal::Device,
+    _device: cpal::Device,
     stream: cpal::Stream,
     config: StreamConfig,
     consumer: ringbuf::Consumer<f32>,
@@ -24,7 +27,7 @@ impl Microphone {
             .supported_input_configs()
             .expect("No supported configs");
         let config = configs_range.next().expect("No supported config");
-        let config = config.with_sample_rate(cpal::SampleRate { 0: 8000 });
+        let config = config.with_sample_rate(cpal::SampleRate(8000));
         let config = StreamConfig::from(config);
 
         let buffer_size: usize = config.sample_rate.0.try_into().unwrap();
@@ -46,7 +49,7 @@ impl Microphone {
             .unwrap();
 
         Microphone {
-            device,
+            _device: device,
             stream,
             config,
             consumer,
@@ -77,49 +80,31 @@ impl Microphone {
             None
         } else {
             let needed_samples = self.needed_samples();
-            let buffer_len = needed_samples.try_into().unwrap();
 
-            let mut samples = vec![0.0; buffer_len];
+            let mut samples = vec![0.0; needed_samples];
             self.consumer.pop_slice(&mut samples);
 
+            let power = samples.iter().map(|sample| sample * sample).sum::<f32>();
+            let voiced = (power / samples.len().max(1) as f32).sqrt() >= SILENCE_LEVEL;
             let freq = self.frequency(&mut samples);
-            let note = (12.0 * (freq / 440.0).log2()).floor() as i32;
-            let note = note as i8;
+            let note = note::frequency_to_pitch(freq);
 
             self.num_samples_processed += needed_samples as u128;
             self.elapsed_time += self.window_length;
-            let length = 1000.0 * samples.len() as f32 / self.config.sample_rate.0 as f32;
 
             Some(Note::new(
                 self.window_length.as_millis() as u32,
                 note,
-                true,
+                voiced,
                 "".to_string(),
             ))
         }
     }
 
-    pub fn clear(&mut self) {
-        self.consumer.pop_each(|_| true, None);
-    }
-
     pub fn set_window_length(&mut self, window_length: time::Duration) {
         self.window_length = window_length;
     }
 
-    fn zero_crossing_rate(&self, v: &Vec<f32>) -> f32 {
-        let mut zero_cross_rate = 0.0;
-        let mut prev = v.get(0).unwrap().clone();
-        for elem in v {
-            if elem < &0.0 && prev >= 0.0 || elem >= &0.0 && prev < 0.0 {
-                zero_cross_rate += 1.0;
-            }
-            prev = *elem;
-        }
-        zero_cross_rate /= (v.len() - 1) as f32;
-        zero_cross_rate
-    }
-
     fn autocorrelate(&mut self, v: &mut Vec<f32>) -> Vec<f32> {
         //let buffer_len = (2.0 * v.len() as f32).log2().ceil() as u32;
         //let buffer_len = 2_usize.pow(buffer_len);
@@ -159,19 +144,14 @@ impl Microphone {
         let auto = self.autocorrelate(samples);
 
         //normalize
-        let variance = auto.get(0).unwrap();
+        let variance = auto[0];
         let auto = auto
             .iter()
             .map(|&elem| elem / variance)
             .collect::<Vec<f32>>();
 
-        let zero_cross_rate = self.zero_crossing_rate(&auto);
-
         //first zero cross
-        let zero_cross = match auto.iter().position(|&elem| elem < 0.0) {
-            None => 0,
-            Some(z) => z,
-        };
+        let zero_cross = auto.iter().position(|&elem| elem < 0.0).unwrap_or_default();
 
         //find max
         let mut max_index: usize = zero_cross;
@@ -182,11 +162,8 @@ impl Microphone {
         }
 
         //convert to frequency
-        let peak = auto.get(max_index).unwrap();
         let rate = self.config.sample_rate.0 as f32;
-        let freq = rate / max_index as f32;
-
-        freq
+        rate / max_index as f32
     }
 
     pub fn ready(&self) -> bool {
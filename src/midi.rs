@@ -0,0 +1,918 @@
+use midly::num::{u15, u24, u28, u4, u7};
+use midly::{Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use crate::format_error::FormatError;
+use crate::note::{self, Note};
+use crate::song::Song;
+use crate::track::{Phrase, Track};
+use crate::track_file::{Resolution, TrackHeader, FORMAT_VERSION};
+
+/// Where the melody lives in a MIDI file.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Melody {
+    /// Every note on this channel (0-15), whichever track it is in.
+    Channel(u8),
+    /// Every note in this track of the file.
+    Track(usize),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ImportOptions {
+    pub melody: Melody,
+    /// Rests at least this long, in milliseconds, start a new phrase.
+    pub phrase_break: u32,
+}
+
+impl ImportOptions {
+    pub fn new(melody: Melody) -> Self {
+        ImportOptions {
+            melody,
+            phrase_break: 2000,
+        }
+    }
+}
+
+/// General MIDI percussion channel, counting from 0.
+const DRUM_CHANNEL: usize = 9;
+
+/// Default MIDI tempo of 120 BPM, in microseconds per beat.
+const DEFAULT_TEMPO: u32 = 500_000;
+
+/// Converts tick positions into milliseconds.
+struct TempoMap {
+    timing: Timing,
+    /// Tempo changes as (tick, microseconds per beat), sorted by tick.
+    changes: Vec<(u64, u32)>,
+}
+
+impl TempoMap {
+    fn new(smf: &Smf) -> Self {
+        let mut changes = vec![];
+        for track in &smf.tracks {
+            let mut tick = 0;
+            for event in track {
+                tick += event.delta.as_int() as u64;
+                if let TrackEventKind::Meta(MetaMessage::Tempo(tempo)) = event.kind {
+                    changes.push((tick, tempo.as_int()));
+                }
+            }
+        }
+        changes.sort_by_key(|&(tick, _)| tick);
+        TempoMap {
+            timing: smf.header.timing,
+            changes,
+        }
+    }
+
+    fn ms(&self, tick: u64) -> f64 {
+        match self.timing {
+            Timing::Metrical(ticks_per_beat) => {
+                let ticks_per_beat = ticks_per_beat.as_int() as f64;
+                let mut ms = 0.0;
+                let mut last_tick = 0;
+                let mut tempo = DEFAULT_TEMPO;
+                for &(change_tick, change_tempo) in &self.changes {
+                    if change_tick >= tick {
+                        break;
+                    }
+                    ms += (change_tick - last_tick) as f64 * tempo as f64 / ticks_per_beat / 1000.0;
+                    last_tick = change_tick;
+                    tempo = change_tempo;
+                }
+                ms + (tick - last_tick) as f64 * tempo as f64 / ticks_per_beat / 1000.0
+            }
+            Timing::Timecode(fps, subframes) => {
+                // A broken header can claim no subframes, which would mean
+                // every tick is forever.
+                let subframes = subframes.max(1);
+                tick as f64 * 1000.0 / (fps.as_f32() as f64 * subframes as f64)
+            }
+        }
+    }
+
+    fn initial_bpm(&self) -> f32 {
+        let tempo = match self.changes.first() {
+            Some(&(0, tempo)) => tempo,
+            _ => DEFAULT_TEMPO,
+        };
+        60_000_000.0 / tempo as f32
+    }
+}
+
+/// A melody note in ticks, before it is turned into a `Note`.
+struct MelodyNote {
+    start: u64,
+    end: u64,
+    key: u8,
+    lyric: String,
+    break_before: bool,
+    break_after: bool,
+}
+
+/// Reads a Standard MIDI File and turns its melody into a track.
+pub fn read(path: &Path, options: ImportOptions) -> Result<Track, FormatError> {
+    let bytes = fs::read(path).map_err(|e| FormatError::io(path, e))?;
+    parse(&bytes, path, options)
+}
+
+/// Builds a track from the melody of a MIDI file.
+///
+/// Overlapping notes are cut where the next one starts, so the melody is
+/// always monophonic. Lyric meta events (FF 05) are attached to the note that
+/// is sounding when they occur, and a line break in a lyric ends the phrase.
+/// Spaces and trailing hyphens in the lyrics mark where words end. Gaps
+/// between notes become unvoiced rests.
+pub fn parse(bytes: &[u8], path: &Path, options: ImportOptions) -> Result<Track, FormatError> {
+    let smf = Smf::parse(bytes).map_err(|e| FormatError::parse(path, 0, &e.to_string()))?;
+    let source_tracks = source_tracks(&smf, path, options.melody)?;
+    let mut lyrics = lyric_events(&smf, &source_tracks);
+    if lyrics.is_empty() {
+        lyrics = lyric_events(&smf, &(0..smf.tracks.len()).collect::<Vec<_>>());
+    }
+    melody_track(&smf, path, &source_tracks, options, lyrics)
+}
+
+/// Reads a `.kar` karaoke MIDI file into a song.
+///
+/// The lyrics are the text events (FF 01) of the track marked `@KMIDI`, or of
+/// the track with the most text, where a leading `/` or `\` starts a new line
+/// and `@` lines hold the title and artist. The melody is the channel whose
+/// notes start together with the most lyrics. The file itself is the song's
+/// audio, so a folder with just a `.kar` in it can be sung.
+pub fn read_kar(path: &Path) -> Result<Song, FormatError> {
+    let bytes = fs::read(path).map_err(|e| FormatError::io(path, e))?;
+    parse_kar(&bytes, path)
+}
+
+/// Parses a `.kar` karaoke MIDI file. See [`read_kar`].
+pub fn parse_kar(bytes: &[u8], path: &Path) -> Result<Song, FormatError> {
+    let smf = Smf::parse(bytes).map_err(|e| FormatError::parse(path, 0, &e.to_string()))?;
+    let (lyrics, tags) = kar_text(&smf);
+    if lyrics.is_empty() {
+        return Err(FormatError::parse(path, 0, "file has no karaoke lyrics"));
+    }
+    let melody = kar_melody(&smf, &lyrics)
+        .ok_or_else(|| FormatError::parse(path, 0, "file has no melody"))?;
+    let source_tracks = source_tracks(&smf, path, melody)?;
+    let track = melody_track(
+        &smf,
+        path,
+        &source_tracks,
+        ImportOptions::new(melody),
+        lyrics,
+    )?;
+
+    // By convention the first @T line is the title and the second the artist.
+    let mut titles = tags
+        .iter()
+        .filter_map(|tag| tag.strip_prefix("@T"))
+        .map(|t| t.trim().to_string());
+    let title = titles.next().filter(|t| !t.is_empty()).unwrap_or_else(|| {
+        path.file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default()
+    });
+    let artist = titles.next().unwrap_or_default();
+    let mut song = Song::new(title, artist, String::new(), None, None);
+    song.audio_path = Some(path.to_path_buf());
+    song.add_track(track.name.clone(), track);
+    Ok(song)
+}
+
+/// A note played by a MIDI file, with its times in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sound {
+    pub start: u32,
+    pub end: u32,
+    pub key: u8,
+}
+
+/// Reads every note a MIDI file plays, so the file can be played back.
+pub fn read_sounds(path: &Path) -> Result<Vec<Sound>, FormatError> {
+    let bytes = fs::read(path).map_err(|e| FormatError::io(path, e))?;
+    parse_sounds(&bytes, path)
+}
+
+/// The notes of every track and channel, sorted by start. Drums are left out,
+/// as they'd only be played as tones.
+pub fn parse_sounds(bytes: &[u8], path: &Path) -> Result<Vec<Sound>, FormatError> {
+    let smf = Smf::parse(bytes).map_err(|e| FormatError::parse(path, 0, &e.to_string()))?;
+    let tempo_map = TempoMap::new(&smf);
+    let ms = |tick: u64| tempo_map.ms(tick).round() as u32;
+    let mut sounds = vec![];
+    for track in &smf.tracks {
+        let mut tick = 0;
+        // When each sounding note started, by channel and key.
+        let mut sounding = HashMap::new();
+        for event in track {
+            tick += event.delta.as_int() as u64;
+            let (channel, message) = match event.kind {
+                TrackEventKind::Midi { channel, message } => (channel.as_int(), message),
+                _ => continue,
+            };
+            if channel as usize == DRUM_CHANNEL {
+                continue;
+            }
+            match message {
+                MidiMessage::NoteOn { key, vel } if vel > 0 => {
+                    sounding.entry((channel, key.as_int())).or_insert(tick);
+                }
+                MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                    let key = key.as_int();
+                    if let Some(start) = sounding.remove(&(channel, key)) {
+                        sounds.push(Sound {
+                            start: ms(start),
+                            end: ms(tick),
+                            key,
+                        });
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+    sounds.sort_by_key(|sound| (sound.start, sound.key));
+    Ok(sounds)
+}
+
+fn source_tracks(smf: &Smf, path: &Path, melody: Melody) -> Result<Vec<usize>, FormatError> {
+    match melody {
+        Melody::Track(i) if i < smf.tracks.len() => Ok(vec![i]),
+        Melody::Track(i) => Err(FormatError::parse(
+            path,
+            0,
+            &format!("file has no track {}", i),
+        )),
+        Melody::Channel(c) if c < 16 => Ok((0..smf.tracks.len()).collect()),
+        Melody::Channel(c) => Err(FormatError::parse(
+            path,
+            0,
+            &format!("there is no channel {}", c),
+        )),
+    }
+}
+
+fn melody_track(
+    smf: &Smf,
+    path: &Path,
+    source_tracks: &[usize],
+    options: ImportOptions,
+    lyrics: Vec<(u64, String)>,
+) -> Result<Track, FormatError> {
+    let tempo_map = TempoMap::new(smf);
+    let mut notes = melody_notes(smf, source_tracks, options.melody);
+    if notes.is_empty() {
+        return Err(FormatError::parse(path, 0, "melody has no notes"));
+    }
+    attach_lyrics(&mut notes, lyrics);
+
+    let mut track = Track::new();
+    track.name = track_name(smf, source_tracks).unwrap_or_else(|| {
+        path.file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default()
+    });
+    track.header = TrackHeader {
+        version: FORMAT_VERSION,
+        bpm: Some(tempo_map.initial_bpm()),
+        gap: tempo_map.ms(notes[0].start).round() as u32,
+        resolution: Resolution::Milliseconds,
+        ..TrackHeader::new()
+    };
+    track.phrases = build_phrases(&notes, &tempo_map, options.phrase_break);
+    Ok(track)
+}
+
+fn melody_notes(smf: &Smf, source_tracks: &[usize], melody: Melody) -> Vec<MelodyNote> {
+    // (tick, is note on, key)
+    let mut events = vec![];
+    for &i in source_tracks {
+        let mut tick = 0;
+        for event in &smf.tracks[i] {
+            tick += event.delta.as_int() as u64;
+            if let TrackEventKind::Midi { channel, message } = event.kind {
+                if let Melody::Channel(c) = melody {
+                    if channel.as_int() != c {
+                        continue;
+                    }
+                }
+                match message {
+                    MidiMessage::NoteOn { key, vel } if vel > 0 => {
+                        events.push((tick, true, key.as_int()))
+                    }
+                    MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                        events.push((tick, false, key.as_int()))
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+    // Note offs go first so back-to-back notes don't cut each other short.
+    events.sort_by_key(|&(tick, on, _)| (tick, on));
+
+    let mut notes = vec![];
+    let mut sounding: Option<(u64, u8)> = None;
+    for (tick, on, key) in events {
+        match sounding {
+            Some((start, sounding_key)) if on || key == sounding_key => {
+                if tick > start {
+                    notes.push(MelodyNote {
+                        start,
+                        end: tick,
+                        key: sounding_key,
+                        lyric: String::new(),
+                        break_before: false,
+                        break_after: false,
+                    });
+                }
+                sounding = None;
+            }
+            _ => (),
+        }
+        if on {
+            sounding = Some((tick, key));
+        }
+    }
+    notes
+}
+
+fn lyric_events(smf: &Smf, source_tracks: &[usize]) -> Vec<(u64, String)> {
+    let mut lyrics = vec![];
+    for &i in source_tracks {
+        let mut tick = 0;
+        for event in &smf.tracks[i] {
+            tick += event.delta.as_int() as u64;
+            if let TrackEventKind::Meta(MetaMessage::Lyric(text)) = event.kind {
+                lyrics.push((tick, String::from_utf8_lossy(text).to_string()));
+            }
+        }
+    }
+    lyrics.sort_by_key(|&(tick, _)| tick);
+    lyrics
+}
+
+/// Splits the text events of a karaoke file into lyrics and `@` tags. The
+/// `/` and `\` line markers are turned into line breaks.
+fn kar_text(smf: &Smf) -> (Vec<(u64, String)>, Vec<String>) {
+    let texts: Vec<Vec<(u64, String)>> = smf
+        .tracks
+        .iter()
+        .map(|track| {
+            let mut tick = 0;
+            let mut texts = vec![];
+            for event in track {
+                tick += event.delta.as_int() as u64;
+                if let TrackEventKind::Meta(MetaMessage::Text(text)) = event.kind {
+                    texts.push((tick, String::from_utf8_lossy(text).to_string()));
+                }
+            }
+            texts
+        })
+        .collect();
+    let words = texts
+        .iter()
+        .position(|track| track.iter().any(|(_, text)| text.starts_with("@KMIDI")))
+        .or_else(|| (0..texts.len()).max_by_key(|&i| texts[i].len()));
+    let words = match words {
+        Some(i) => &texts[i],
+        None => return (vec![], vec![]),
+    };
+
+    let mut lyrics = vec![];
+    let mut tags = vec![];
+    for (tick, text) in words {
+        if text.starts_with('@') {
+            tags.push(text.clone());
+        } else if let Some(line) = text.strip_prefix(['/', '\\']) {
+            lyrics.push((*tick, format!("\r{}", line)));
+        } else {
+            lyrics.push((*tick, text.clone()));
+        }
+    }
+    (lyrics, tags)
+}
+
+/// Picks the channel whose note ons land on the most lyric ticks, leaving
+/// out the drum channel.
+fn kar_melody(smf: &Smf, lyrics: &[(u64, String)]) -> Option<Melody> {
+    let lyric_ticks: HashSet<u64> = lyrics.iter().map(|(tick, _)| *tick).collect();
+    let mut hits = [0; 16];
+    let mut notes = [0; 16];
+    for track in &smf.tracks {
+        let mut tick = 0;
+        for event in track {
+            tick += event.delta.as_int() as u64;
+            if let TrackEventKind::Midi {
+                channel,
+                message: MidiMessage::NoteOn { vel, .. },
+            } = event.kind
+            {
+                let c = channel.as_int() as usize;
+                if vel > 0 && c != DRUM_CHANNEL {
+                    notes[c] += 1;
+                    if lyric_ticks.contains(&tick) {
+                        hits[c] += 1;
+                    }
+                }
+            }
+        }
+    }
+    (0..16)
+        .filter(|&c| notes[c] > 0)
+        .max_by_key(|&c| (hits[c], notes[c]))
+        .map(|c| Melody::Channel(c as u8))
+}
+
+/// Appends every lyric to the last note starting at or before it. A line
+/// break that leads the first lyric at a note's start breaks the phrase
+/// before that note. Any other line break ends the phrase after it.
+fn attach_lyrics(notes: &mut [MelodyNote], lyrics: Vec<(u64, String)>) {
+    let is_break = |c: char| c == '\r' || c == '\n';
+    for (tick, text) in lyrics {
+        let i = notes
+            .partition_point(|note| note.start <= tick)
+            .saturating_sub(1);
+        let note = &mut notes[i];
+        let trimmed = text.trim_start_matches(is_break);
+        if trimmed.len() < text.len() {
+            if note.lyric.is_empty() && tick <= note.start {
+                note.break_before = true;
+            } else {
+                note.break_after = true;
+            }
+        }
+        if trimmed.contains(is_break) {
+            note.break_after = true;
+        }
+        note.lyric += &trimmed.replace(is_break, "");
+    }
+}
+
+fn track_name(smf: &Smf, source_tracks: &[usize]) -> Option<String> {
+    source_tracks.iter().find_map(|&i| {
+        smf.tracks[i].iter().find_map(|event| match event.kind {
+            TrackEventKind::Meta(MetaMessage::TrackName(name)) if !name.is_empty() => {
+                Some(String::from_utf8_lossy(name).to_string())
+            }
+            _ => None,
+        })
+    })
+}
+
+fn build_phrases(notes: &[MelodyNote], tempo_map: &TempoMap, phrase_break: u32) -> Vec<Phrase> {
+    // Lengths are differences of rounded absolute times so they don't drift.
+    let ms = |tick: u64| tempo_map.ms(tick).round() as u32;
+    let mut phrases = vec![];
+    let mut phrase: Phrase = vec![];
+    for (i, note) in notes.iter().enumerate() {
+        if note.break_before && !phrase.is_empty() {
+            phrases.push(std::mem::take(&mut phrase));
+        }
+        let pitch = note.key as i8;
+        let end = ms(note.end);
+        phrase.push(Note::new(
+            end - ms(note.start),
+            pitch,
+            true,
+            note.lyric.clone(),
+        ));
+        if let Some(next) = notes.get(i + 1) {
+            let rest = ms(next.start).saturating_sub(end);
+            if rest > 0 {
+                phrase.push(Note::new(rest, pitch, false, "".to_string()));
+            }
+            if note.break_after || rest >= phrase_break {
+                phrases.push(std::mem::take(&mut phrase));
+            }
+        }
+    }
+    if !phrase.is_empty() {
+        phrases.push(phrase);
+    }
+    for phrase in &mut phrases {
+        note::mark_word_boundaries(phrase);
+    }
+    phrases
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ExportOptions {
+    pub bpm: f32,
+    pub ticks_per_beat: u16,
+    /// Channel (0-15) the melody is played on.
+    pub channel: u8,
+}
+
+impl ExportOptions {
+    pub fn new(bpm: f32) -> Self {
+        ExportOptions {
+            bpm,
+            ticks_per_beat: 480,
+            channel: 0,
+        }
+    }
+}
+
+/// Events of the exported melody track. They are declared in the order they
+/// are written when several fall on the same tick.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Event {
+    NoteOff(u8),
+    Marker(String),
+    Lyric(String),
+    NoteOn(u8),
+}
+
+/// Writes `track` as a Standard MIDI File.
+pub fn write(track: &Track, options: ExportOptions, path: &Path) -> Result<(), FormatError> {
+    let bytes = serialize(track, options).map_err(|reason| FormatError::parse(path, 0, &reason))?;
+    fs::write(path, bytes).map_err(|e| FormatError::io(path, e))
+}
+
+/// Serializes `track` as a format 1 MIDI file with a tempo track and a melody
+/// track.
+///
+/// Voiced notes become note on/off pairs and unvoiced notes are left silent.
+/// Every lyric is written as a lyric meta event at the start of its note,
+/// followed by a space when the next lyric starts a new word or a carriage
+/// return at the end of the phrase, and each phrase starts with a marker.
+/// The track's gap is kept as leading silence.
+pub fn serialize(track: &Track, options: ExportOptions) -> Result<Vec<u8>, String> {
+    if !(options.bpm.is_finite() && options.bpm >= 4.0) {
+        return Err("BPM must be at least 4".to_string());
+    }
+    if options.ticks_per_beat == 0 || options.ticks_per_beat > 0x7fff {
+        return Err("ticks per beat must be between 1 and 32767".to_string());
+    }
+    if options.channel > 15 {
+        return Err(format!("there is no channel {}", options.channel));
+    }
+    let ticks_per_ms = options.bpm as f64 * options.ticks_per_beat as f64 / 60000.0;
+    let tick = |position: u32| {
+        let ms = track.header.gap as f64 + track.header.position_to_ms(position);
+        (ms * ticks_per_ms).round() as u64
+    };
+
+    let mut events = vec![];
+    let mut position = 0;
+    for (p, phrase) in track.phrases.iter().enumerate() {
+        events.push((tick(position), Event::Marker(format!("Phrase {}", p + 1))));
+        for (i, note) in phrase.iter().enumerate() {
+            let start = tick(position);
+            let end = tick(position + note.length);
+            if !note.lyric.is_empty() {
+                let mut text = note.lyric.clone() + note::lyric_separator(&phrase[i..]);
+                if phrase[i + 1..].iter().all(|next| next.lyric.is_empty()) {
+                    text.push('\r');
+                }
+                events.push((start, Event::Lyric(text)));
+            }
+            if note.voiced {
+                let key = note.pitch.clamp(0, 127) as u8;
+                events.push((start, Event::NoteOn(key)));
+                events.push((end, Event::NoteOff(key)));
+            }
+            position += note.length;
+        }
+    }
+    events.sort();
+
+    let channel = u4::new(options.channel);
+    let velocity = u7::new(100);
+    let mut melody = vec![TrackEvent {
+        delta: u28::new(0),
+        kind: TrackEventKind::Meta(MetaMessage::TrackName(track.name.as_bytes())),
+    }];
+    let mut last_tick = 0;
+    for (tick, event) in &events {
+        let kind = match event {
+            Event::NoteOff(key) => TrackEventKind::Midi {
+                channel,
+                message: MidiMessage::NoteOff {
+                    key: u7::new(*key),
+                    vel: u7::new(0),
+                },
+            },
+            Event::Marker(text) => TrackEventKind::Meta(MetaMessage::Marker(text.as_bytes())),
+            Event::Lyric(text) => TrackEventKind::Meta(MetaMessage::Lyric(text.as_bytes())),
+            Event::NoteOn(key) => TrackEventKind::Midi {
+                channel,
+                message: MidiMessage::NoteOn {
+                    key: u7::new(*key),
+                    vel: velocity,
+                },
+            },
+        };
+        let delta = u32::try_from(tick - last_tick)
+            .ok()
+            .and_then(u28::try_from)
+            .ok_or_else(|| "notes are too far apart for a MIDI file".to_string())?;
+        melody.push(TrackEvent { delta, kind });
+        last_tick = *tick;
+    }
+    melody.push(end_of_track());
+
+    let tempo = (60_000_000.0 / options.bpm as f64).round() as u32;
+    let conductor = vec![
+        TrackEvent {
+            delta: u28::new(0),
+            kind: TrackEventKind::Meta(MetaMessage::Tempo(u24::new(tempo))),
+        },
+        end_of_track(),
+    ];
+
+    let mut smf = Smf::new(Header::new(
+        Format::Parallel,
+        Timing::Metrical(u15::new(options.ticks_per_beat)),
+    ));
+    smf.tracks = vec![conductor, melody];
+    let mut bytes = vec![];
+    smf.write_std(&mut bytes).map_err(|e| e.to_string())?;
+    Ok(bytes)
+}
+
+fn end_of_track() -> TrackEvent<'static> {
+    TrackEvent {
+        delta: u28::new(0),
+        kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use midly::Fps;
+    use std::path::PathBuf;
+
+    fn note_on(channel: u8, key: u8) -> TrackEventKind<'static> {
+        TrackEventKind::Midi {
+            channel: u4::new(channel),
+            message: MidiMessage::NoteOn {
+                key: u7::new(key),
+                vel: u7::new(100),
+            },
+        }
+    }
+
+    fn note_off(channel: u8, key: u8) -> TrackEventKind<'static> {
+        TrackEventKind::Midi {
+            channel: u4::new(channel),
+            message: MidiMessage::NoteOff {
+                key: u7::new(key),
+                vel: u7::new(0),
+            },
+        }
+    }
+
+    fn tempo(microseconds: u32) -> TrackEventKind<'static> {
+        TrackEventKind::Meta(MetaMessage::Tempo(u24::new(microseconds)))
+    }
+
+    /// A file at 480 ticks per beat with events given as absolute ticks.
+    fn smf_bytes(tracks: Vec<Vec<(u32, TrackEventKind<'static>)>>) -> Vec<u8> {
+        let mut smf = Smf::new(Header::new(
+            Format::Parallel,
+            Timing::Metrical(u15::new(480)),
+        ));
+        for events in tracks {
+            let mut last = 0;
+            let mut track: Vec<TrackEvent> = events
+                .into_iter()
+                .map(|(tick, kind)| {
+                    let delta = u28::new(tick - last);
+                    last = tick;
+                    TrackEvent { delta, kind }
+                })
+                .collect();
+            track.push(end_of_track());
+            smf.tracks.push(track);
+        }
+        let mut bytes = vec![];
+        smf.write_std(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn voiced(track: &Track) -> Vec<(i8, u32)> {
+        track
+            .phrases
+            .iter()
+            .flatten()
+            .filter(|note| note.voiced)
+            .map(|note| (note.pitch, note.length))
+            .collect()
+    }
+
+    #[test]
+    fn picks_melody_by_track_or_channel() {
+        let bytes = smf_bytes(vec![
+            vec![
+                (0, tempo(500_000)),
+                (0, note_on(1, 50)),
+                (480, note_off(1, 50)),
+            ],
+            vec![
+                (0, note_on(0, 60)),
+                (480, note_off(0, 60)),
+                (480, note_on(0, 62)),
+                (960, note_off(0, 62)),
+            ],
+            vec![(960, note_on(1, 52)), (1440, note_off(1, 52))],
+        ]);
+        let path = Path::new("song.mid");
+        let import = |melody| parse(&bytes, path, ImportOptions::new(melody));
+
+        let track = import(Melody::Track(1)).unwrap();
+        assert_eq!(voiced(&track), [(60, 500), (62, 500)]);
+        let track = import(Melody::Channel(1)).unwrap();
+        assert_eq!(voiced(&track), [(50, 500), (52, 500)]);
+        assert_eq!(track.phrases[0][1].length, 500);
+        assert!(!track.phrases[0][1].voiced);
+        assert!(import(Melody::Track(3)).is_err());
+        assert!(import(Melody::Channel(16)).is_err());
+    }
+
+    #[test]
+    fn converts_across_tempo_changes() {
+        let bytes = smf_bytes(vec![
+            vec![(0, tempo(500_000)), (480, tempo(250_000))],
+            vec![
+                (480, note_on(0, 60)),
+                (960, note_off(0, 60)),
+                (960, note_on(0, 62)),
+                (1920, note_off(0, 62)),
+            ],
+        ]);
+        let track = parse(
+            &bytes,
+            Path::new("song.mid"),
+            ImportOptions::new(Melody::Track(1)),
+        )
+        .unwrap();
+        assert_eq!(track.header.bpm, Some(120.0));
+        assert_eq!(track.header.gap, 500);
+        assert_eq!(voiced(&track), [(60, 250), (62, 500)]);
+    }
+
+    #[test]
+    fn exports_events_in_order() {
+        let mut track = Track::new();
+        track.name = "Lead".to_string();
+        track.header.gap = 500;
+        track.phrases = vec![
+            vec![
+                Note::new(500, 60, true, "Hel".to_string()),
+                Note::new(500, 62, true, "lo".to_string()).with_continues(true),
+            ],
+            vec![
+                Note::new(250, 0, false, String::new()),
+                Note::new(250, 64, true, "world".to_string()),
+            ],
+        ];
+        let bytes = serialize(&track, ExportOptions::new(120.0)).unwrap();
+        let smf = Smf::parse(&bytes).unwrap();
+        assert_eq!(smf.header.timing, Timing::Metrical(u15::new(480)));
+        let mut tick = 0;
+        let events: Vec<(u32, String)> = smf.tracks[1]
+            .iter()
+            .map(|event| {
+                tick += event.delta.as_int();
+                let text = match event.kind {
+                    TrackEventKind::Midi {
+                        message: MidiMessage::NoteOn { key, .. },
+                        ..
+                    } => format!("on {}", key),
+                    TrackEventKind::Midi {
+                        message: MidiMessage::NoteOff { key, .. },
+                        ..
+                    } => format!("off {}", key),
+                    TrackEventKind::Meta(MetaMessage::Lyric(text)) => {
+                        format!("lyric {:?}", String::from_utf8_lossy(text))
+                    }
+                    TrackEventKind::Meta(MetaMessage::Marker(text)) => {
+                        format!("marker {}", String::from_utf8_lossy(text))
+                    }
+                    TrackEventKind::Meta(MetaMessage::TrackName(name)) => {
+                        format!("name {}", String::from_utf8_lossy(name))
+                    }
+                    TrackEventKind::Meta(MetaMessage::EndOfTrack) => "end".to_string(),
+                    _ => "other".to_string(),
+                };
+                (tick, text)
+            })
+            .collect();
+        let expected = [
+            (0, "name Lead"),
+            (480, "marker Phrase 1"),
+            (480, "lyric \"Hel\""),
+            (480, "on 60"),
+            (960, "off 60"),
+            (960, "lyric \"lo\\r\""),
+            (960, "on 62"),
+            (1440, "off 62"),
+            (1440, "marker Phrase 2"),
+            (1680, "lyric \"world\\r\""),
+            (1680, "on 64"),
+            (1920, "off 64"),
+            (1920, "end"),
+        ];
+        let expected: Vec<(u32, String)> = expected
+            .iter()
+            .map(|(tick, text)| (*tick, text.to_string()))
+            .collect();
+        assert_eq!(events, expected);
+
+        // Importing the file again gets the same notes back.
+        let read = parse(
+            &bytes,
+            Path::new("song.mid"),
+            ImportOptions::new(Melody::Track(1)),
+        )
+        .unwrap();
+        assert_eq!(voiced(&read), [(60, 500), (62, 500), (64, 250)]);
+        let lyrics: Vec<Vec<(&str, bool)>> = read
+            .phrases
+            .iter()
+            .map(|phrase| {
+                phrase
+                    .iter()
+                    .filter(|note| note.voiced)
+                    .map(|note| (note.lyric.as_str(), note.continues))
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            lyrics,
+            [vec![("Hel", false), ("lo", true)], vec![("world", false)]]
+        );
+    }
+
+    #[test]
+    fn rejects_gaps_too_long_for_midi() {
+        let mut track = Track::new();
+        track.header.gap = u32::MAX;
+        track.phrases = vec![vec![Note::new(500, 60, true, String::new())]];
+        assert!(serialize(&track, ExportOptions::new(120.0)).is_err());
+    }
+
+    #[test]
+    fn survives_timecode_without_subframes() {
+        let tempo_map = TempoMap {
+            timing: Timing::Timecode(Fps::Fps25, 0),
+            changes: vec![],
+        };
+        assert_eq!(tempo_map.ms(100), 4000.0);
+    }
+
+    #[test]
+    fn reads_karaoke_files() {
+        let text = |s: &'static str| TrackEventKind::Meta(MetaMessage::Text(s.as_bytes()));
+        let bytes = smf_bytes(vec![
+            vec![(0, tempo(500000))],
+            vec![
+                (0, text("@KMIDI KARAOKE FILE")),
+                (0, text("@TSong")),
+                (0, text("@TSinger")),
+                (480, text("Hel")),
+                (960, text("lo ")),
+                (1920, text("/world")),
+            ],
+            vec![
+                (480, note_on(9, 36)),
+                (480, note_on(2, 60)),
+                (960, note_off(2, 60)),
+                (960, note_on(2, 62)),
+                (1440, note_off(2, 62)),
+                (1440, note_off(9, 36)),
+                (1920, note_on(2, 64)),
+                (2400, note_off(2, 64)),
+            ],
+        ]);
+        let song = parse_kar(&bytes, Path::new("song.kar")).unwrap();
+        assert_eq!(song.name, "Song");
+        assert_eq!(song.artist, "Singer");
+        assert_eq!(song.audio_path, Some(PathBuf::from("song.kar")));
+        let track = song.tracks.values().next().unwrap();
+        assert_eq!(track.header.gap, 500);
+        let lyrics: Vec<Vec<&str>> = track
+            .phrases
+            .iter()
+            .map(|phrase| phrase.iter().map(|note| note.lyric.as_str()).collect())
+            .collect();
+        assert_eq!(lyrics, [vec!["Hel", "lo", ""], vec!["world"]]);
+        assert_eq!(voiced(track), [(60, 500), (62, 500), (64, 500)]);
+
+        let sound = |start, end, key| Sound { start, end, key };
+        assert_eq!(
+            parse_sounds(&bytes, Path::new("song.kar")).unwrap(),
+            [
+                sound(500, 1000, 60),
+                sound(1000, 1500, 62),
+                sound(2000, 2500, 64)
+            ]
+        );
+    }
+}
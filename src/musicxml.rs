@@ -0,0 +1,540 @@
+use roxmltree::{Document, Node};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
+use crate::format_error::FormatError;
+use crate::note::Note;
+use crate::track::{Phrase, Track};
+use crate::track_file::{Resolution, TrackHeader, FORMAT_VERSION};
+
+/// Which part of the score holds the melody.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Part {
+    /// Position in the score, starting at 0.
+    Index(usize),
+    /// Part id (such as `P1`) or part name (such as `Soprano`).
+    Name(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    pub part: Part,
+    /// Voice within the part. The first voice that has a note is used if
+    /// this is `None`.
+    pub voice: Option<String>,
+    /// Rests at least this long, in milliseconds, start a new phrase.
+    pub phrase_break: u32,
+}
+
+impl ImportOptions {
+    pub fn new(part: Part) -> Self {
+        ImportOptions {
+            part,
+            voice: None,
+            phrase_break: 2000,
+        }
+    }
+}
+
+/// Tempo used until the score sets one, in quarter notes per minute.
+const DEFAULT_TEMPO: f64 = 120.0;
+
+/// A note or rest of the melody with its times in milliseconds.
+struct Event {
+    start: f64,
+    end: f64,
+    /// `None` for rests.
+    pitch: Option<i8>,
+    lyric: String,
+    /// The lyric is a later syllable of the previous lyric's word.
+    continues: bool,
+    tied: bool,
+    /// A slide or glissando starts here and ends at the next note.
+    slides: bool,
+}
+
+/// Reads a `.musicxml` file, or a compressed `.mxl` archive, into a track.
+pub fn read(path: &Path, options: &ImportOptions) -> Result<Track, FormatError> {
+    let xml = match path.extension() {
+        Some(ext) if ext == "mxl" => read_compressed(path)?,
+        _ => fs::read_to_string(path).map_err(|e| FormatError::io(path, e))?,
+    };
+    parse(&xml, path, options)
+}
+
+/// Extracts the score from an `.mxl` archive, using the root file named in
+/// `META-INF/container.xml` when there is one.
+fn read_compressed(path: &Path) -> Result<String, FormatError> {
+    let zip_error = |e: zip::result::ZipError| FormatError::parse(path, 0, &e.to_string());
+    let file = File::open(path).map_err(|e| FormatError::io(path, e))?;
+    let mut archive = zip::ZipArchive::new(file).map_err(zip_error)?;
+
+    let mut read_entry = |name: &str| -> Result<String, FormatError> {
+        let mut s = String::new();
+        archive
+            .by_name(name)
+            .map_err(zip_error)?
+            .read_to_string(&mut s)
+            .map_err(|e| FormatError::io(path, e))?;
+        Ok(s)
+    };
+    let root = read_entry("META-INF/container.xml")
+        .ok()
+        .and_then(|container| {
+            let doc = Document::parse(&container).ok()?;
+            let rootfile = doc.descendants().find(|n| n.has_tag_name("rootfile"))?;
+            rootfile.attribute("full-path").map(|p| p.to_string())
+        });
+    let root = match root {
+        Some(root) => root,
+        None => archive
+            .file_names()
+            .find(|name| {
+                !name.starts_with("META-INF/")
+                    && (name.ends_with(".xml") || name.ends_with(".musicxml"))
+            })
+            .map(|name| name.to_string())
+            .ok_or_else(|| FormatError::parse(path, 0, "archive has no score"))?,
+    };
+    let mut s = String::new();
+    archive
+        .by_name(&root)
+        .map_err(zip_error)?
+        .read_to_string(&mut s)
+        .map_err(|e| FormatError::io(path, e))?;
+    Ok(s)
+}
+
+/// Builds a track from one part of a partwise MusicXML score.
+///
+/// Durations are converted with the score's divisions and tempo marks. Only
+/// the first note of a chord is kept, grace notes are skipped and tied notes
+/// are merged. The first lyric of each note becomes its lyric, and its
+/// syllabic type says whether it continues the previous word. Slides and
+/// glissandos glide to the pitch of the following note. Rests become
+/// unvoiced notes, and rests of at least `phrase_break` end the phrase.
+pub fn parse(xml: &str, path: &Path, options: &ImportOptions) -> Result<Track, FormatError> {
+    let doc = Document::parse(xml).map_err(|e| FormatError::parse(path, 0, &e.to_string()))?;
+    let score = doc.root_element();
+    if !score.has_tag_name("score-partwise") {
+        return Err(FormatError::parse(
+            path,
+            0,
+            "only partwise scores are supported",
+        ));
+    }
+
+    let part_names: Vec<(String, String)> = score
+        .descendants()
+        .filter(|n| n.has_tag_name("score-part"))
+        .map(|n| {
+            let id = n.attribute("id").unwrap_or_default().to_string();
+            let name = child_text(n, "part-name").unwrap_or_default().to_string();
+            (id, name)
+        })
+        .collect();
+    let parts: Vec<Node> = score
+        .children()
+        .filter(|n| n.has_tag_name("part"))
+        .collect();
+    let part = match &options.part {
+        Part::Index(i) => parts.get(*i).copied(),
+        Part::Name(name) => parts.iter().copied().find(|part| {
+            let id = part.attribute("id").unwrap_or_default();
+            id == name || part_names.iter().any(|(i, n)| i == id && n == name)
+        }),
+    }
+    .ok_or_else(|| FormatError::parse(path, 0, "score has no such part"))?;
+
+    let (events, tempo) = melody_events(part, options.voice.as_deref());
+    let first_note = events
+        .iter()
+        .position(|event| event.pitch.is_some())
+        .ok_or_else(|| FormatError::parse(path, 0, "part has no notes"))?;
+
+    let mut track = Track::new();
+    let id = part.attribute("id").unwrap_or_default();
+    track.name = part_names
+        .iter()
+        .find(|(i, name)| i == id && !name.is_empty())
+        .map(|(_, name)| name.clone())
+        .unwrap_or_else(|| id.to_string());
+    track.header = TrackHeader {
+        version: FORMAT_VERSION,
+        bpm: Some(tempo as f32),
+        gap: events[first_note].start.round() as u32,
+        resolution: Resolution::Milliseconds,
+        ..TrackHeader::new()
+    };
+    track.phrases = build_phrases(&events[first_note..], options.phrase_break);
+    Ok(track)
+}
+
+/// Collects the notes and rests of one voice of a part, along with the first
+/// tempo of the part. Other voices still move time along, and `<backup>` and
+/// `<forward>` move it back and forth, so the voice keeps its place in
+/// measures it shares. Time where the voice has nothing written is a rest.
+fn melody_events(part: Node, voice: Option<&str>) -> (Vec<Event>, f64) {
+    let mut events = vec![];
+    let mut voice = voice.map(|v| v.to_string());
+    let mut divisions = 1.0;
+    let mut tempo = DEFAULT_TEMPO;
+    let mut first_tempo = None;
+    let mut time: f64 = 0.0;
+    // Where the voice's last note or rest ended.
+    let mut voice_end = 0.0;
+
+    for measure in part.children().filter(|n| n.has_tag_name("measure")) {
+        let measure_start = time;
+        let mut measure_end = time;
+        for element in measure.children().filter(|n| n.is_element()) {
+            let marked_tempo = element
+                .descendants()
+                .filter(|n| n.has_tag_name("sound"))
+                .find_map(|n| n.attribute("tempo"))
+                .and_then(|t| t.parse::<f64>().ok())
+                .filter(|t| *t > 0.0);
+            if let Some(t) = marked_tempo {
+                tempo = t;
+                first_tempo.get_or_insert(t);
+            }
+            let duration = || {
+                let divisions_long: f64 = child_text(element, "duration")
+                    .and_then(|d| d.parse().ok())
+                    .unwrap_or(0.0);
+                divisions_long / divisions * 60000.0 / tempo
+            };
+            match element.tag_name().name() {
+                "attributes" => {
+                    if let Some(d) = child_text(element, "divisions").and_then(|d| d.parse().ok()) {
+                        divisions = d;
+                    }
+                }
+                "note" => {
+                    if has_child(element, "grace") || has_child(element, "chord") {
+                        continue;
+                    }
+                    let start = time;
+                    time += duration();
+                    measure_end = measure_end.max(time);
+                    let note_voice = child_text(element, "voice").unwrap_or("1").to_string();
+                    if *voice.get_or_insert_with(|| note_voice.clone()) != note_voice {
+                        continue;
+                    }
+                    if start > voice_end {
+                        events.push(Event {
+                            start: voice_end,
+                            end: start,
+                            pitch: None,
+                            lyric: String::new(),
+                            continues: false,
+                            tied: false,
+                            slides: false,
+                        });
+                    }
+                    let (lyric, continues) = lyric(element);
+                    events.push(Event {
+                        start: start.max(voice_end),
+                        end: time.max(voice_end),
+                        pitch: pitch(element),
+                        lyric,
+                        continues,
+                        tied: element
+                            .children()
+                            .any(|n| n.has_tag_name("tie") && n.attribute("type") == Some("stop")),
+                        slides: element.descendants().any(|n| {
+                            (n.has_tag_name("slide") || n.has_tag_name("glissando"))
+                                && n.attribute("type") == Some("start")
+                        }),
+                    });
+                    voice_end = time.max(voice_end);
+                }
+                "backup" => time = (time - duration()).max(measure_start),
+                "forward" => time += duration(),
+                _ => (),
+            }
+            measure_end = measure_end.max(time);
+        }
+        // The next measure starts after the longest voice of this one.
+        time = measure_end;
+    }
+    (events, first_tempo.unwrap_or(DEFAULT_TEMPO))
+}
+
+fn child_text<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.children()
+        .find(|n| n.has_tag_name(name))
+        .and_then(|n| n.text())
+        .map(|t| t.trim())
+}
+
+fn has_child(node: Node, name: &str) -> bool {
+    node.children().any(|n| n.has_tag_name(name))
+}
+
+/// MIDI note number of a `<note>`, or `None` for rests and unpitched notes.
+fn pitch(note: Node) -> Option<i8> {
+    let pitch = note.children().find(|n| n.has_tag_name("pitch"))?;
+    let step = match child_text(pitch, "step")? {
+        "C" => 0,
+        "D" => 2,
+        "E" => 4,
+        "F" => 5,
+        "G" => 7,
+        "A" => 9,
+        "B" => 11,
+        _ => return None,
+    };
+    let alter = child_text(pitch, "alter")
+        .and_then(|a| a.parse::<f64>().ok())
+        .unwrap_or(0.0)
+        .round() as i32;
+    let octave = child_text(pitch, "octave")?.parse::<i32>().ok()?;
+    Some(((octave + 1) * 12 + step + alter).clamp(0, 127) as i8)
+}
+
+/// Text of the first verse's lyric, and whether it's a middle or final
+/// syllable of a word.
+fn lyric(note: Node) -> (String, bool) {
+    let lyrics: Vec<Node> = note
+        .children()
+        .filter(|n| n.has_tag_name("lyric"))
+        .collect();
+    let lyric = match lyrics
+        .iter()
+        .find(|n| n.attribute("number").unwrap_or("1") == "1")
+        .or_else(|| lyrics.first())
+    {
+        Some(lyric) => *lyric,
+        None => return (String::new(), false),
+    };
+    let text: Vec<&str> = lyric
+        .children()
+        .filter(|n| n.has_tag_name("text"))
+        .filter_map(|n| n.text())
+        .collect();
+    let continues = matches!(child_text(lyric, "syllabic"), Some("middle" | "end"));
+    (text.join(" "), continues)
+}
+
+fn build_phrases(events: &[Event], phrase_break: u32) -> Vec<Phrase> {
+    // Lengths are differences of rounded absolute times so they don't drift.
+    let length = |start: f64, end: f64| (end.round() - start.round()).max(0.0) as u32;
+    let mut phrases = vec![];
+    let mut phrase: Phrase = vec![];
+    let mut last_end = events.first().map_or(0.0, |event| event.start);
+    let mut rest_start = None;
+    let mut pitch = 60;
+
+    for (i, event) in events.iter().enumerate() {
+        let note_pitch = match event.pitch {
+            Some(p) => p,
+            None => {
+                rest_start.get_or_insert(event.start);
+                last_end = event.end;
+                continue;
+            }
+        };
+        if let Some(start) = rest_start.take() {
+            let rest = length(start, event.start);
+            if rest > 0 {
+                phrase.push(Note::new(rest, pitch, false, "".to_string()));
+            }
+            if rest >= phrase_break {
+                phrases.push(std::mem::take(&mut phrase));
+            }
+        }
+        let continues_tie = event.tied && event.start == last_end;
+        let slide_to = if event.slides {
+            events[i + 1..].iter().find_map(|next| next.pitch)
+        } else {
+            None
+        };
+        match phrase.last_mut() {
+            Some(last) if continues_tie && last.voiced && last.pitch == note_pitch => {
+                last.length += length(event.start, event.end);
+                last.slide_to = slide_to;
+            }
+            _ => phrase.push(
+                Note::new(
+                    length(event.start, event.end),
+                    note_pitch,
+                    true,
+                    event.lyric.clone(),
+                )
+                .with_continues(event.continues)
+                .with_slide(slide_to),
+            ),
+        }
+        pitch = note_pitch;
+        last_end = event.end;
+    }
+    if !phrase.is_empty() {
+        phrases.push(phrase);
+    }
+    phrases
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A soprano line with two voices, a tie across a backup and a measure
+    /// the melody sits out, over a piano part.
+    const SCORE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<score-partwise version="3.1">
+  <part-list>
+    <score-part id="P1"><part-name>Soprano</part-name></score-part>
+    <score-part id="P2"><part-name>Piano</part-name></score-part>
+  </part-list>
+  <part id="P1">
+    <measure number="1">
+      <attributes><divisions>2</divisions></attributes>
+      <direction><sound tempo="120"/></direction>
+      <note>
+        <pitch><step>C</step><octave>4</octave></pitch>
+        <duration>2</duration><voice>1</voice>
+        <lyric number="1"><syllabic>begin</syllabic><text>Hel</text></lyric>
+      </note>
+      <note>
+        <pitch><step>D</step><octave>4</octave></pitch>
+        <duration>2</duration><voice>1</voice><tie type="start"/>
+        <lyric number="1"><syllabic>end</syllabic><text>lo</text></lyric>
+      </note>
+      <backup><duration>4</duration></backup>
+      <note>
+        <pitch><step>G</step><octave>3</octave></pitch>
+        <duration>8</duration><voice>2</voice>
+      </note>
+      <backup><duration>4</duration></backup>
+      <note>
+        <pitch><step>D</step><octave>4</octave></pitch>
+        <duration>2</duration><voice>1</voice><tie type="stop"/>
+      </note>
+      <forward><duration>2</duration></forward>
+    </measure>
+    <measure number="2">
+      <note>
+        <pitch><step>G</step><octave>3</octave></pitch>
+        <duration>8</duration><voice>2</voice>
+      </note>
+    </measure>
+    <measure number="3">
+      <note>
+        <pitch><step>G</step><octave>3</octave></pitch>
+        <duration>8</duration><voice>2</voice>
+      </note>
+      <backup><duration>8</duration></backup>
+      <note>
+        <pitch><step>E</step><alter>-1</alter><octave>4</octave></pitch>
+        <duration>4</duration><voice>1</voice>
+        <lyric number="1"><syllabic>single</syllabic><text>world</text></lyric>
+      </note>
+      <note><rest/><duration>4</duration><voice>1</voice></note>
+    </measure>
+  </part>
+  <part id="P2">
+    <measure number="1">
+      <attributes><divisions>1</divisions></attributes>
+      <note><rest/><duration>1</duration><voice>1</voice></note>
+      <note>
+        <pitch><step>A</step><octave>2</octave></pitch>
+        <duration>3</duration><voice>1</voice>
+      </note>
+    </measure>
+  </part>
+</score-partwise>
+"#;
+
+    /// (length, pitch, lyric, continues) of each note, with rests as `None`.
+    type Notes<'a> = Vec<Vec<(u32, Option<i8>, &'a str, bool)>>;
+
+    fn notes(track: &Track) -> Notes<'_> {
+        track
+            .phrases
+            .iter()
+            .map(|phrase| {
+                phrase
+                    .iter()
+                    .map(|note| {
+                        let pitch = note.voiced.then_some(note.pitch);
+                        (note.length, pitch, note.lyric.as_str(), note.continues)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn import(part: Part) -> Result<Track, FormatError> {
+        parse(
+            SCORE,
+            Path::new("score.musicxml"),
+            &ImportOptions::new(part),
+        )
+    }
+
+    #[test]
+    fn follows_one_voice_through_backups() {
+        let track = import(Part::Index(0)).unwrap();
+        assert_eq!(track.name, "Soprano");
+        assert_eq!(track.header.bpm, Some(120.0));
+        assert_eq!(track.header.gap, 0);
+        assert_eq!(
+            notes(&track),
+            [
+                vec![
+                    (500, Some(60), "Hel", false),
+                    (1000, Some(62), "lo", true),
+                    (2500, None, "", false),
+                ],
+                vec![(1000, Some(63), "world", false)],
+            ]
+        );
+    }
+
+    #[test]
+    fn picks_parts() {
+        let piano = import(Part::Name("Piano".to_string())).unwrap();
+        assert_eq!(piano.header.gap, 500);
+        assert_eq!(notes(&piano), [vec![(1500, Some(45), "", false)]]);
+        assert_eq!(import(Part::Index(1)).unwrap().phrases, piano.phrases);
+        assert_eq!(
+            import(Part::Name("P2".to_string())).unwrap().phrases,
+            piano.phrases
+        );
+        assert!(import(Part::Index(2)).is_err());
+
+        let options = ImportOptions {
+            voice: Some("2".to_string()),
+            ..ImportOptions::new(Part::Index(0))
+        };
+        let low = parse(SCORE, Path::new("score.musicxml"), &options).unwrap();
+        assert_eq!(notes(&low), [vec![(2000, Some(55), "", false); 3]]);
+    }
+
+    #[test]
+    fn reads_compressed_scores() {
+        let path = std::env::temp_dir().join(format!("karaoke-test-{}.mxl", std::process::id()));
+        let mut archive = zip::ZipWriter::new(File::create(&path).unwrap());
+        let options = zip::write::FileOptions::default();
+        archive
+            .start_file("META-INF/container.xml", options)
+            .unwrap();
+        std::io::Write::write_all(
+            &mut archive,
+            br#"<container><rootfiles><rootfile full-path="score/song.xml"/></rootfiles></container>"#,
+        )
+        .unwrap();
+        archive.start_file("score/song.xml", options).unwrap();
+        std::io::Write::write_all(&mut archive, SCORE.as_bytes()).unwrap();
+        archive.finish().unwrap();
+
+        let track = read(&path, &ImportOptions::new(Part::Index(0)));
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            track.unwrap().phrases,
+            import(Part::Index(0)).unwrap().phrases
+        );
+    }
+}
@@ -1,9 +1,17 @@
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Note {
     pub length: u32,
+    /// MIDI note number: 60 is middle C (C4) and 69 is A4.
     pub pitch: i8,
+    /// Pitch the note glides to by its end, if it slides.
+    pub slide_to: Option<i8>,
     pub voiced: bool,
     pub lyric: String,
+    /// How a voiced note is scored. Ignored for rests.
+    pub kind: NoteKind,
+    /// The lyric is a later syllable of the previous lyric's word, so no
+    /// space goes between them.
+    pub continues: bool,
 }
 
 impl Note {
@@ -11,8 +19,295 @@ impl Note {
         Note {
             length,
             pitch,
+            slide_to: None,
             voiced,
             lyric,
+            kind: NoteKind::Normal,
+            continues: false,
         }
     }
+
+    pub fn with_kind(mut self, kind: NoteKind) -> Note {
+        self.kind = kind;
+        self
+    }
+
+    pub fn with_continues(mut self, continues: bool) -> Note {
+        self.continues = continues;
+        self
+    }
+
+    pub fn with_slide(mut self, slide_to: Option<i8>) -> Note {
+        self.slide_to = slide_to;
+        self
+    }
+
+    pub fn end_pitch(&self) -> i8 {
+        self.slide_to.unwrap_or(self.pitch)
+    }
+
+    /// Splits the note into one `length` long and one for the rest. The
+    /// lyric is divided between them in proportion, and a slide is split
+    /// at the pitch it has reached. Returns `None` unless both parts would
+    /// have some length.
+    pub fn split(&self, length: u32) -> Option<(Note, Note)> {
+        if length == 0 || length >= self.length {
+            return None;
+        }
+        let fraction = length as f32 / self.length as f32;
+        let chars = self.lyric.chars().count();
+        let cut = ((chars as f32 * fraction).round() as usize).clamp(chars.min(1), chars);
+        let at = self
+            .lyric
+            .char_indices()
+            .nth(cut)
+            .map_or(self.lyric.len(), |(i, _)| i);
+        let middle = self.pitch_at(fraction).round() as i8;
+        let first = Note {
+            length,
+            lyric: self.lyric[..at].to_string(),
+            ..self.clone()
+        }
+        .with_slide(Some(middle).filter(|middle| *middle != self.pitch));
+        let second = Note {
+            length: self.length - length,
+            pitch: middle,
+            lyric: self.lyric[at..].to_string(),
+            continues: at < self.lyric.len(),
+            ..self.clone()
+        }
+        .with_slide(self.slide_to.filter(|end| *end != middle));
+        Some((first, second))
+    }
+
+    /// Pitch `fraction` of the way through the note, gliding in a straight
+    /// line for slides.
+    pub fn pitch_at(&self, fraction: f32) -> f32 {
+        let fraction = fraction.clamp(0.0, 1.0);
+        self.pitch as f32 + (self.end_pitch() as f32 - self.pitch as f32) * fraction
+    }
+}
+
+/// Whether the first of `notes` has a lyric whose word goes on in a later
+/// note's lyric.
+pub fn joins_next(notes: &[Note]) -> bool {
+    let (note, rest) = match notes.split_first() {
+        Some(split) => split,
+        None => return false,
+    };
+    let next = rest.iter().find(|next| !next.lyric.is_empty());
+    !note.lyric.is_empty() && next.is_some_and(|next| next.continues)
+}
+
+/// What goes after the lyric of the first of `notes` so the phrase reads as
+/// words: a space if another word follows, otherwise nothing.
+pub fn lyric_separator(notes: &[Note]) -> &'static str {
+    let has_next_lyric = notes.iter().skip(1).any(|next| !next.lyric.is_empty());
+    match notes.first() {
+        Some(note) if !note.lyric.is_empty() && has_next_lyric && !joins_next(notes) => " ",
+        _ => "",
+    }
+}
+
+/// The lyrics of a phrase joined into words.
+pub fn phrase_lyrics(phrase: &[Note]) -> String {
+    (0..phrase.len())
+        .map(|i| phrase[i].lyric.clone() + lyric_separator(&phrase[i..]))
+        .collect()
+}
+
+/// Works out `continues` for lyrics that mark word boundaries the way
+/// UltraStar and karaoke MIDI files do, then tidies the lyrics up. A space
+/// at the end of one syllable or the start of the next separates words, and
+/// so does a new phrase. A trailing `-` joins a syllable to the next one and
+/// is removed.
+pub fn mark_word_boundaries(phrase: &mut [Note]) {
+    let mut joins_next = false;
+    let mut first = true;
+    for note in phrase.iter_mut().filter(|note| !note.lyric.is_empty()) {
+        let starts_word = note.lyric.starts_with(char::is_whitespace);
+        note.continues = !first && joins_next && !starts_word;
+        joins_next = !note.lyric.ends_with(char::is_whitespace);
+        first = false;
+        let mut lyric = note.lyric.trim();
+        if lyric.len() > 1 && lyric.ends_with('-') {
+            lyric = &lyric[..lyric.len() - 1];
+            joins_next = true;
+        }
+        note.lyric = lyric.to_string();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoteKind {
+    Normal,
+    /// Worth double.
+    Golden,
+    /// Sung however the singer likes and not scored.
+    Freestyle,
+    /// Spoken in rhythm, so only timing is scored and not pitch.
+    Rap,
+}
+
+impl NoteKind {
+    pub const ALL: [NoteKind; 4] = [
+        NoteKind::Normal,
+        NoteKind::Golden,
+        NoteKind::Freestyle,
+        NoteKind::Rap,
+    ];
+
+    /// How many points singing the note is worth, relative to a normal note.
+    pub fn weight(self) -> u32 {
+        match self {
+            NoteKind::Normal | NoteKind::Rap => 1,
+            NoteKind::Golden => 2,
+            NoteKind::Freestyle => 0,
+        }
+    }
+
+    /// Whether the sung pitch has to match the note's.
+    pub fn scores_pitch(self) -> bool {
+        matches!(self, NoteKind::Normal | NoteKind::Golden)
+    }
+
+    /// The kind after this one, wrapping around.
+    pub fn next(self) -> NoteKind {
+        let i = NoteKind::ALL.iter().position(|kind| *kind == self).unwrap();
+        NoteKind::ALL[(i + 1) % NoteKind::ALL.len()]
+    }
+}
+
+/// MIDI note number of A4, which sounds at 440 Hz.
+pub const A4: i8 = 69;
+
+/// Length of notes added in the editor, before they are timed.
+pub const DEFAULT_LENGTH: u32 = 8;
+
+pub const MIN_PITCH: i8 = 0;
+pub const MAX_PITCH: i8 = 127;
+
+const PITCH_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+/// How a sung pitch is compared with the pitch it should have been.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PitchMatch {
+    /// The octave has to match as well.
+    Exact,
+    /// Any octave counts, so everyone can sing a part in their own range.
+    AnyOctave,
+}
+
+impl PitchMatch {
+    /// Semitones from `expected` up to `sung`. With `AnyOctave` this is the
+    /// distance to the nearest octave of `expected`, between -6 and 6.
+    pub fn difference(self, expected: i8, sung: i8) -> i32 {
+        let difference = sung as i32 - expected as i32;
+        match self {
+            PitchMatch::Exact => difference,
+            PitchMatch::AnyOctave => (difference + 6).rem_euclid(12) - 6,
+        }
+    }
+}
+
+/// Frequency in Hz of a pitch, which may fall between semitones.
+pub fn pitch_to_frequency(pitch: f32) -> f32 {
+    440.0 * 2f32.powf((pitch - A4 as f32) / 12.0)
+}
+
+/// The pitch nearest to `frequency`, clamped to the MIDI range.
+pub fn frequency_to_pitch(frequency: f32) -> i8 {
+    let pitch = A4 as f32 + 12.0 * (frequency / 440.0).log2();
+    if pitch.is_nan() {
+        return MIN_PITCH;
+    }
+    pitch.round().clamp(MIN_PITCH as f32, MAX_PITCH as f32) as i8
+}
+
+pub fn octave(pitch: i8) -> i32 {
+    (pitch as i32).div_euclid(12) - 1
+}
+
+/// Scientific pitch notation with sharps, such as `C#4` for 61.
+pub fn pitch_name(pitch: i8) -> String {
+    let class = (pitch as i32).rem_euclid(12) as usize;
+    format!("{}{}", PITCH_NAMES[class], octave(pitch))
+}
+
+/// Parses names like `C4`, `c#4`, `Eb3` or `B-1`. Any number of `#` and `b`
+/// accidentals may follow the letter.
+pub fn parse_pitch_name(s: &str) -> Option<i8> {
+    let mut chars = s.trim().chars();
+    let mut pitch: i32 = match chars.next()?.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    let octave_start = rest.find(|c| c != '#' && c != 'b').unwrap_or(rest.len());
+    for accidental in rest[..octave_start].chars() {
+        pitch += if accidental == '#' { 1 } else { -1 };
+    }
+    let octave = rest[octave_start..].parse::<i32>().ok()?;
+    let pitch = (octave + 1) * 12 + pitch;
+    if (MIN_PITCH as i32..=MAX_PITCH as i32).contains(&pitch) {
+        Some(pitch as i8)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pitch_names_round_trip() {
+        for pitch in MIN_PITCH..=MAX_PITCH {
+            assert_eq!(parse_pitch_name(&pitch_name(pitch)), Some(pitch));
+        }
+        assert_eq!(pitch_name(60), "C4");
+        assert_eq!(parse_pitch_name("Eb3"), Some(51));
+        assert_eq!(parse_pitch_name("H4"), None);
+    }
+
+    #[test]
+    fn frequency_round_trip() {
+        assert_eq!(pitch_to_frequency(A4 as f32), 440.0);
+        for pitch in MIN_PITCH..=MAX_PITCH {
+            assert_eq!(frequency_to_pitch(pitch_to_frequency(pitch as f32)), pitch);
+        }
+        assert_eq!(frequency_to_pitch(0.0), MIN_PITCH);
+    }
+
+    #[test]
+    fn joins_syllables_into_words() {
+        let lyric = |text: &str| Note::new(1, 60, true, text.to_string());
+        let mut phrase = vec![
+            lyric("beau"),
+            lyric("ti-"),
+            Note::new(1, 60, false, String::new()),
+            lyric("ful "),
+            lyric("day"),
+            lyric(" long"),
+        ];
+        mark_word_boundaries(&mut phrase);
+        assert_eq!(phrase_lyrics(&phrase), "beautiful day long");
+        assert!(phrase[1].continues && phrase[3].continues);
+        assert!(!phrase[4].continues && !phrase[5].continues);
+    }
+
+    #[test]
+    fn any_octave_difference() {
+        assert_eq!(PitchMatch::Exact.difference(60, 48), -12);
+        assert_eq!(PitchMatch::AnyOctave.difference(60, 48), 0);
+        assert_eq!(PitchMatch::AnyOctave.difference(60, 71), -1);
+    }
 }
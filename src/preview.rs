@@ -0,0 +1,341 @@
+use rodio::{Decoder, OutputStream, Sink, Source};
+use std::f32::consts::TAU;
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::iter::Peekable;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::format_error::FormatError;
+use crate::midi::{self, Sound};
+use crate::note::{self, Note};
+use crate::track::{NoteIndex, Track};
+
+const SAMPLE_RATE: u32 = 44100;
+/// Loudness of the tones, out of 1.
+const VOLUME: f32 = 0.2;
+/// Tones fade in and out over this many samples so they don't click.
+const FADE: f32 = 200.0;
+/// Loudness of each note of a MIDI file, which often plays several at once.
+const MIDI_VOLUME: f32 = 0.05;
+
+/// Notes of a track played back as sine tones, or the song's own audio, so
+/// a chart can be heard while it is edited. Playback stops when this is
+/// dropped.
+pub struct Preview {
+    // Sound only plays while the stream is alive.
+    _stream: OutputStream,
+    sink: Sink,
+    started: Instant,
+    /// Where in the track playback started, in milliseconds from the start
+    /// of the audio.
+    start_ms: u32,
+}
+
+impl Preview {
+    /// Starts playing the notes of `track` in `range`. Returns `None` if
+    /// there are no notes in it.
+    pub fn play(
+        track: &Track,
+        range: RangeInclusive<NoteIndex>,
+    ) -> Result<Option<Self>, PreviewError> {
+        let indices: Vec<NoteIndex> = track
+            .phrases
+            .iter()
+            .enumerate()
+            .flat_map(|(p, phrase)| (0..phrase.len()).map(move |n| (p, n)))
+            .filter(|index| range.contains(index))
+            .collect();
+        let first = match indices.first() {
+            Some(first) => *first,
+            None => return Ok(None),
+        };
+        let notes = indices
+            .iter()
+            .map(|&(p, n)| (track.phrases[p][n].clone(), track.note_length_ms((p, n))))
+            .collect::<Vec<_>>();
+
+        let (stream, handle) = OutputStream::try_default()?;
+        let sink = Sink::try_new(&handle)?;
+        sink.append(Tones {
+            notes: notes.into_iter(),
+            current: None,
+            sample: 0,
+            phase: 0.0,
+        });
+        Ok(Some(Preview {
+            _stream: stream,
+            sink,
+            started: Instant::now(),
+            start_ms: track.note_time(first),
+        }))
+    }
+
+    /// Starts playing the audio file at `path` from `start_ms` milliseconds
+    /// in.
+    pub fn play_audio(path: &Path, start_ms: u32) -> Result<Self, PreviewError> {
+        let file = File::open(path).map_err(|e| PreviewError::Io(path.to_path_buf(), e))?;
+        let audio = Decoder::new(BufReader::new(file))
+            .map_err(|e| PreviewError::Decode(path.to_path_buf(), e))?
+            .skip_duration(Duration::from_millis(start_ms.into()));
+        let (stream, handle) = OutputStream::try_default()?;
+        let sink = Sink::try_new(&handle)?;
+        sink.append(audio);
+        Ok(Preview {
+            _stream: stream,
+            sink,
+            started: Instant::now(),
+            start_ms,
+        })
+    }
+
+    /// Starts playing a song's backing audio from the start. MIDI files,
+    /// such as `.kar` songs, are played as tones.
+    pub fn play_song(path: &Path) -> Result<Self, PreviewError> {
+        let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+        if !matches!(extension.as_deref(), Some("kar" | "mid" | "midi")) {
+            return Preview::play_audio(path, 0);
+        }
+        let sounds = midi::read_sounds(path).map_err(PreviewError::Midi)?;
+        let (stream, handle) = OutputStream::try_default()?;
+        let sink = Sink::try_new(&handle)?;
+        sink.append(Synth::new(sounds));
+        Ok(Preview {
+            _stream: stream,
+            sink,
+            started: Instant::now(),
+            start_ms: 0,
+        })
+    }
+
+    pub fn is_playing(&self) -> bool {
+        !self.sink.empty()
+    }
+
+    /// Where in the track playback has got to, in milliseconds from the
+    /// start of the audio, or `None` once it has finished.
+    pub fn position(&self) -> Option<u32> {
+        if !self.is_playing() {
+            return None;
+        }
+        Some(self.start_ms + self.started.elapsed().as_millis() as u32)
+    }
+}
+
+/// Sine tones for notes and how many milliseconds each lasts, generated as
+/// they play. Rests are silent and slides glide.
+struct Tones {
+    notes: std::vec::IntoIter<(Note, u32)>,
+    /// The note playing and how many samples long it is.
+    current: Option<(Note, u32)>,
+    sample: u32,
+    phase: f32,
+}
+
+impl Iterator for Tones {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let (note, samples) = loop {
+            match &self.current {
+                Some((note, samples)) if self.sample < *samples => break (note, *samples),
+                _ => {
+                    let (note, ms) = self.notes.next()?;
+                    let samples = (ms as u64 * SAMPLE_RATE as u64 / 1000) as u32;
+                    self.current = Some((note, samples));
+                    self.sample = 0;
+                }
+            }
+        };
+        let fraction = self.sample as f32 / samples as f32;
+        let fade = (self.sample.min(samples - self.sample) as f32 / FADE).min(1.0);
+        self.sample += 1;
+        if !note.voiced {
+            return Some(0.0);
+        }
+        let frequency = note::pitch_to_frequency(note.pitch_at(fraction));
+        self.phase = (self.phase + TAU * frequency / SAMPLE_RATE as f32) % TAU;
+        Some(self.phase.sin() * VOLUME * fade)
+    }
+}
+
+impl Source for Tones {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Sine tones for the notes of a MIDI file, mixed as they play.
+struct Synth {
+    /// Notes yet to start, as (start, end, frequency) with times in samples.
+    sounds: Peekable<std::vec::IntoIter<(u64, u64, f32)>>,
+    /// Notes sounding, as (start, end, frequency, phase).
+    playing: Vec<(u64, u64, f32, f32)>,
+    sample: u64,
+}
+
+impl Synth {
+    fn new(sounds: Vec<Sound>) -> Self {
+        let samples = |ms: u32| ms as u64 * SAMPLE_RATE as u64 / 1000;
+        let sounds: Vec<(u64, u64, f32)> = sounds
+            .into_iter()
+            .map(|sound| {
+                let frequency = note::pitch_to_frequency(sound.key as f32);
+                (samples(sound.start), samples(sound.end), frequency)
+            })
+            .collect();
+        Synth {
+            sounds: sounds.into_iter().peekable(),
+            playing: vec![],
+            sample: 0,
+        }
+    }
+}
+
+impl Iterator for Synth {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        while let Some(&(start, end, frequency)) = self.sounds.peek() {
+            if start > self.sample {
+                break;
+            }
+            self.playing.push((start, end, frequency, 0.0));
+            self.sounds.next();
+        }
+        let sample = self.sample;
+        self.playing.retain(|&(_, end, _, _)| end > sample);
+        if self.playing.is_empty() && self.sounds.peek().is_none() {
+            return None;
+        }
+        let mut mix = 0.0;
+        for (start, end, frequency, phase) in &mut self.playing {
+            let fade = ((sample - *start).min(*end - sample) as f32 / FADE).min(1.0);
+            mix += phase.sin() * fade;
+            *phase = (*phase + TAU * *frequency / SAMPLE_RATE as f32) % TAU;
+        }
+        self.sample += 1;
+        Some((mix * MIDI_VOLUME).clamp(-1.0, 1.0))
+    }
+}
+
+impl Source for Synth {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[derive(Debug)]
+pub enum PreviewError {
+    Io(PathBuf, std::io::Error),
+    Decode(PathBuf, rodio::decoder::DecoderError),
+    Midi(FormatError),
+    Stream(rodio::StreamError),
+    Play(rodio::PlayError),
+}
+
+impl fmt::Display for PreviewError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreviewError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            PreviewError::Decode(path, e) => write!(f, "{}: {}", path.display(), e),
+            PreviewError::Midi(e) => e.fmt(f),
+            PreviewError::Stream(e) => write!(f, "no audio output: {}", e),
+            PreviewError::Play(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for PreviewError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PreviewError::Io(_, e) => Some(e),
+            PreviewError::Decode(_, e) => Some(e),
+            PreviewError::Midi(e) => Some(e),
+            PreviewError::Stream(e) => Some(e),
+            PreviewError::Play(e) => Some(e),
+        }
+    }
+}
+
+impl From<rodio::StreamError> for PreviewError {
+    fn from(e: rodio::StreamError) -> Self {
+        PreviewError::Stream(e)
+    }
+}
+
+impl From<rodio::PlayError> for PreviewError {
+    fn from(e: rodio::PlayError) -> Self {
+        PreviewError::Play(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rests_are_silent() {
+        let notes = vec![
+            (Note::new(1, note::A4, false, String::new()), 10),
+            (Note::new(1, note::A4, true, String::new()), 20),
+        ];
+        let tones = Tones {
+            notes: notes.into_iter(),
+            current: None,
+            sample: 0,
+            phase: 0.0,
+        };
+        let samples: Vec<f32> = tones.collect();
+        assert_eq!(samples.len(), 30 * SAMPLE_RATE as usize / 1000);
+        let (rest, tone) = samples.split_at(10 * SAMPLE_RATE as usize / 1000);
+        assert!(rest.iter().all(|sample| *sample == 0.0));
+        assert!(tone.iter().any(|sample| *sample != 0.0));
+        assert!(tone.iter().all(|sample| sample.abs() <= VOLUME));
+    }
+
+    #[test]
+    fn mixes_midi_notes() {
+        let sound = |start, end, key| Sound { start, end, key };
+        let synth = Synth::new(vec![
+            sound(10, 20, 69),
+            sound(10, 30, 73),
+            sound(40, 50, 76),
+        ]);
+        let samples: Vec<f32> = synth.collect();
+        let ms = |ms: usize| ms * SAMPLE_RATE as usize / 1000;
+        assert_eq!(samples.len(), ms(50));
+        assert!(samples[..ms(10)].iter().all(|sample| *sample == 0.0));
+        assert!(samples[ms(10)..ms(30)].iter().any(|sample| *sample != 0.0));
+        assert!(samples[ms(30)..ms(40)].iter().all(|sample| *sample == 0.0));
+        assert!(samples
+            .iter()
+            .all(|sample| sample.abs() <= 2.0 * MIDI_VOLUME));
+    }
+}
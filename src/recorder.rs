@@ -0,0 +1,142 @@
+use cpal::traits::HostTrait;
+use std::time::Duration;
+
+use crate::mic::Microphone;
+use crate::note::Note;
+
+/// How much of the microphone's audio is turned into each pitch reading.
+const WINDOW_LENGTH: Duration = Duration::from_millis(50);
+/// Notes shorter than this, in milliseconds, are blips and are folded into
+/// the note before them.
+const MIN_NOTE_LENGTH: u32 = 150;
+
+/// Records someone singing, so a melody can be entered by singing it.
+pub struct Recorder {
+    mic: Microphone,
+    /// One reading per window heard so far.
+    windows: Vec<Note>,
+}
+
+impl Recorder {
+    /// Starts recording from the default microphone, or returns `None` if
+    /// there isn't one.
+    pub fn start() -> Option<Self> {
+        let device = cpal::default_host().default_input_device()?;
+        let mut mic = Microphone::new(device);
+        mic.set_window_length(WINDOW_LENGTH);
+        mic.play();
+        Some(Recorder {
+            mic,
+            windows: vec![],
+        })
+    }
+
+    /// Takes in what the microphone has heard since the last call. This has
+    /// to be called more often than once a second or audio is lost.
+    pub fn poll(&mut self) {
+        while self.mic.ready() {
+            if let Some(window) = self.mic.consume() {
+                self.windows.push(window);
+            }
+        }
+    }
+
+    /// Stops recording and returns what was sung as notes with lengths in
+    /// milliseconds.
+    pub fn finish(mut self) -> Vec<Note> {
+        self.poll();
+        self.mic.pause();
+        quantize(&self.windows, MIN_NOTE_LENGTH)
+    }
+}
+
+/// Turns pitch readings into notes. Readings in a row with the same pitch,
+/// or in a row of silence, become one note. Notes shorter than
+/// `min_length` are blips and are added onto the note before them instead.
+pub fn quantize(windows: &[Note], min_length: u32) -> Vec<Note> {
+    let mut notes: Vec<Note> = vec![];
+    // Blips at the very start, with no note before them to go onto.
+    let mut leading = 0;
+    for run in runs(windows) {
+        if run.length < min_length {
+            match notes.last_mut() {
+                Some(last) => last.length += run.length,
+                None => leading += run.length,
+            }
+            continue;
+        }
+        match notes.last_mut() {
+            // Dropping a blip can leave two runs of the same pitch together.
+            Some(last) if same_pitch(last, &run) => last.length += run.length,
+            _ => notes.push(run),
+        }
+    }
+    match notes.first_mut() {
+        Some(first) => first.length += leading,
+        None if leading > 0 => notes.push(Note::new(leading, 0, false, String::new())),
+        None => {}
+    }
+    notes
+}
+
+/// Readings joined into runs of the same pitch.
+fn runs(windows: &[Note]) -> Vec<Note> {
+    let mut runs: Vec<Note> = vec![];
+    for window in windows {
+        match runs.last_mut() {
+            Some(last) if same_pitch(last, window) => last.length += window.length,
+            _ => runs.push(window.clone()),
+        }
+    }
+    runs
+}
+
+/// Whether two readings are the same pitch, or both silence.
+fn same_pitch(a: &Note, b: &Note) -> bool {
+    a.voiced == b.voiced && (!a.voiced || a.pitch == b.pitch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(pitch: i8) -> Note {
+        Note::new(50, pitch, true, String::new())
+    }
+
+    fn silence() -> Note {
+        Note::new(50, 0, false, String::new())
+    }
+
+    #[test]
+    fn merges_readings_and_drops_blips() {
+        let windows = [
+            window(62),
+            window(60),
+            window(60),
+            window(60),
+            window(64),
+            window(60),
+            window(60),
+            silence(),
+            silence(),
+            silence(),
+            window(67),
+            window(67),
+            window(67),
+        ];
+        let notes = quantize(&windows, 150);
+        let summary: Vec<_> = notes
+            .iter()
+            .map(|note| (note.length, note.voiced, note.pitch))
+            .collect();
+        assert_eq!(summary, [(350, true, 60), (150, false, 0), (150, true, 67)]);
+    }
+
+    #[test]
+    fn only_blips_become_a_rest() {
+        let notes = quantize(&[window(60), window(62)], 150);
+        assert_eq!(notes.len(), 1);
+        assert_eq!((notes[0].length, notes[0].voiced), (100, false));
+    }
+}
@@ -1,4 +1,5 @@
 use eframe::egui;
+use serde::Deserialize;
 use std::{
     collections::HashMap,
     path::{Path, PathBuf},
@@ -10,6 +11,15 @@ pub type Name = String;
 pub type Artist = String;
 pub type Album = String;
 
+/// What a track is for, as set in the song's manifest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Lead,
+    Harmony,
+    Backing,
+}
+
 #[derive(Clone)]
 pub struct Song {
     pub name: Name,
@@ -18,6 +28,14 @@ pub struct Song {
     pub tracks: HashMap<String, Track>,
     pub album_cover: Option<Image>,
     pub video_path: Option<PathBuf>,
+    pub audio_path: Option<PathBuf>,
+    pub year: Option<u32>,
+    pub genre: Option<String>,
+    pub language: Option<String>,
+    /// Where the library preview starts, in milliseconds.
+    pub preview_start: Option<u32>,
+    /// Roles of the tracks that have one, by track name.
+    pub roles: HashMap<String, Role>,
 }
 
 impl Song {
@@ -35,6 +53,12 @@ impl Song {
             tracks: HashMap::new(),
             album_cover,
             video_path,
+            audio_path: None,
+            year: None,
+            genre: None,
+            language: None,
+            preview_start: None,
+            roles: HashMap::new(),
         }
     }
 
@@ -42,8 +66,23 @@ impl Song {
         self.tracks.insert(name, track);
     }
 
-    pub fn num_tracks(&self) -> usize {
-        self.tracks.len()
+    /// The track to sing: the one the manifest makes the lead, or else the
+    /// first by name that isn't a backing track, or else the first by name.
+    pub fn lead_track(&self) -> Option<&Track> {
+        self.lead_track_name().map(|name| &self.tracks[name])
+    }
+
+    /// Name of [`Song::lead_track`].
+    pub fn lead_track_name(&self) -> Option<&String> {
+        let mut names: Vec<&String> = self.tracks.keys().collect();
+        names.sort();
+        let role = |name: &String| self.roles.get(name).copied();
+        names
+            .iter()
+            .find(|name| role(name) == Some(Role::Lead))
+            .or_else(|| names.iter().find(|name| role(name) != Some(Role::Backing)))
+            .or_else(|| names.first())
+            .copied()
     }
 }
 
@@ -88,3 +127,37 @@ impl Image {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(names: &[&str]) -> Song {
+        let mut song = Song::new(String::new(), String::new(), String::new(), None, None);
+        for name in names {
+            let mut track = Track::new();
+            track.name = name.to_string();
+            song.add_track(name.to_string(), track);
+        }
+        song
+    }
+
+    fn lead_name(song: &Song) -> Option<&str> {
+        song.lead_track().map(|track| track.name.as_str())
+    }
+
+    #[test]
+    fn picks_lead_track_by_role() {
+        let mut song = song(&["Bass", "Choir", "Alto"]);
+        assert_eq!(lead_name(&song), Some("Alto"));
+        song.roles.insert("Alto".to_string(), Role::Backing);
+        assert_eq!(lead_name(&song), Some("Bass"));
+        song.roles.insert("Choir".to_string(), Role::Lead);
+        assert_eq!(lead_name(&song), Some("Choir"));
+
+        let mut backing = self::song(&["Drums"]);
+        backing.roles.insert("Drums".to_string(), Role::Backing);
+        assert_eq!(lead_name(&backing), Some("Drums"));
+        assert_eq!(lead_name(&self::song(&[])), None);
+    }
+}
@@ -1,7 +1,16 @@
-use std::path::Path;
+use std::fs;
+use std::path::{Path, PathBuf};
 
+use crate::lrc;
+use crate::manifest::Manifest;
+use crate::midi;
+use crate::musicxml::{self, ImportOptions, Part};
 use crate::song::{self, Song};
 use crate::track::Track;
+use crate::ultrastar;
+
+/// Extensions of the audio files that can be a song's backing track.
+const AUDIO_EXTENSIONS: [&str; 4] = ["mp3", "ogg", "wav", "flac"];
 
 pub struct SongLibrary {
     pub songs: Vec<Song>,
@@ -22,66 +31,122 @@ impl SongLibrary {
         let mut songs = Vec::new();
 
         if let Ok(dir_iter) = path.read_dir() {
-            for dir in dir_iter {
-                if let Ok(dir_entry_ok) = dir {
-                    if let Ok(song) = SongLibrary::read_song(dir_entry_ok.path().as_path()) {
-                        songs.push(song);
-                    }
+            for dir_entry_ok in dir_iter.flatten() {
+                if let Ok(song) = SongLibrary::read_song(dir_entry_ok.path().as_path()) {
+                    songs.push(song);
                 }
             }
         }
         songs
     }
 
-    fn read_song(path: &Path) -> Result<Song, std::io::Error> {
-        let dir_iter = path.read_dir()?;
+    /// Reads the song in a song folder, with its manifest applied.
+    pub fn read_song(path: &Path) -> Result<Song, std::io::Error> {
+        let mut song = SongLibrary::read_song_files(path)?;
+        match Manifest::read_dir(path) {
+            Ok(Some(manifest)) => manifest.apply(&mut song, path),
+            Ok(None) => (),
+            Err(e) => eprintln!("Couldn't read song manifest: {}", e),
+        }
+        Ok(song)
+    }
+
+    /// The backing audio of the song in `dir`: the file its manifest names,
+    /// or else the first audio file in the folder.
+    pub fn find_audio(dir: &Path) -> Option<PathBuf> {
+        match Manifest::read_dir(dir) {
+            Ok(Some(Manifest {
+                audio: Some(audio), ..
+            })) => return Some(dir.join(audio)),
+            Ok(_) => (),
+            Err(e) => eprintln!("Couldn't read song manifest: {}", e),
+        }
+        let mut files: Vec<PathBuf> = dir.read_dir().ok()?.flatten().map(|e| e.path()).collect();
+        files.sort();
+        files.into_iter().find(|path| is_audio(path))
+    }
+
+    /// Builds a song from the tracks and media found in a song folder.
+    ///
+    /// `.track`, `.lrc` and MusicXML files come first, with the first part of
+    /// a score as its melody. A folder without them is imported from its
+    /// first UltraStar chart, or else its first `.kar` file. Files are taken
+    /// in name order so a folder always gives the same song.
+    fn read_song_files(path: &Path) -> Result<Song, std::io::Error> {
+        let mut files = path
+            .read_dir()?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<PathBuf>, _>>()?;
+        files.sort();
         let mut tracks: Vec<Track> = vec![];
+        let mut charts = vec![];
+        let mut kars = vec![];
         let mut img = None;
         let mut video_path = None;
-        for dir in dir_iter {
-            let path = dir?.path();
-            let extension = path.extension();
-            match extension {
-                Some(s) => {
-                    let ext = s.to_string_lossy();
-                    if ext == "track" {
-                        let track = Track::read(path.as_path());
-                        match track {
-                            Ok(t) => tracks.push(t),
-                            Err(_) => continue,
+        let mut audio_path = None;
+        for path in files {
+            let ext = match path.extension() {
+                Some(ext) => ext.to_string_lossy().to_lowercase(),
+                None => continue,
+            };
+            match ext.as_str() {
+                "track" => match Track::read_lenient(&path) {
+                    Ok((t, warnings)) => {
+                        for warning in warnings {
+                            eprintln!("Skipping note: {}", warning);
                         }
-                    } else if ext == "png" || ext == "jpg" || ext == "jpeg" {
-                        // TODO defer image loading until needed
-                        // very slow on debug target
-                        let mut unloaded_image = song::Image::new();
-                        unloaded_image.load_image(&path);
-                        img = Some(unloaded_image);
-                    } else if ext == "webm" || ext == "mkv" || ext == "mp4" {
-                        video_path = Some(path.to_path_buf());
+                        tracks.push(t);
                     }
+                    Err(e) => eprintln!("Couldn't read track: {}", e),
+                },
+                "lrc" => match lrc::read(&path) {
+                    Ok(t) => tracks.push(t),
+                    Err(e) => eprintln!("Couldn't read lyrics: {}", e),
+                },
+                "musicxml" | "mxl" => {
+                    match musicxml::read(&path, &ImportOptions::new(Part::Index(0))) {
+                        Ok(t) => tracks.push(t),
+                        Err(e) => eprintln!("Couldn't import score: {}", e),
+                    }
+                }
+                "txt" => charts.push(path),
+                "kar" => kars.push(path),
+                "png" | "jpg" | "jpeg" if img.is_none() => {
+                    // TODO defer image loading until needed
+                    // very slow on debug target
+                    let mut unloaded_image = song::Image::new();
+                    unloaded_image.load_image(&path);
+                    img = Some(unloaded_image);
                 }
-                None => (),
+                "webm" | "mkv" | "mp4" => video_path = video_path.or(Some(path)),
+                _ if is_audio(&path) => audio_path = audio_path.or(Some(path)),
+                _ => (),
             }
         }
-        if tracks.len() > 0 {
-            // TODO load song metadata from fs
-            let mut song = Song::new(
-                tracks.get(0).unwrap().name.clone(),
-                "ARTIST NAME PLACEHOLDER".to_string(),
-                "ALBUM NAME PLACEHOLDER".to_string(),
-                img,
-                video_path,
-            );
+        let mut song = if let Some(first) = tracks.first() {
+            let mut song = Song::new(first.name.clone(), String::new(), String::new(), None, None);
             for track in tracks {
                 song.add_track(track.name.clone(), track);
             }
-            Ok(song)
+            song
+        } else if let Some(song) = charts.iter().find_map(|path| read_chart(path)) {
+            song
+        } else if let Some(song) = kars.iter().find_map(|path| match midi::read_kar(path) {
+            Ok(song) => Some(song),
+            Err(e) => {
+                eprintln!("Couldn't import karaoke file: {}", e);
+                None
+            }
+        }) {
+            song
         } else {
-            Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Could not read track information",
-            ))
-        }
+            return Err(std::io::Error::other("Could not read track information"));
+        };
+        // What an imported file names itself wins over what's in the folder.
+        song.album_cover = song.album_cover.or(img);
+        song.video_path = song.video_path.or(video_path);
+        song.audio_path = song.audio_path.or(audio_path);
+        Ok(song)
     }
 
     // TODO Handle the case of empty libraries
@@ -108,3 +173,30 @@ impl SongLibrary {
         }
     }
 }
+
+/// Imports the UltraStar chart at `path`, or returns `None` if it isn't
+/// one. Other text files, like a README, are skipped without complaint.
+fn read_chart(path: &Path) -> Option<Song> {
+    let s = match fs::read(path) {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        Err(e) => {
+            eprintln!("Couldn't read {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    if !ultrastar::is_chart(&s) {
+        return None;
+    }
+    match ultrastar::parse(&s, path) {
+        Ok(song) => Some(song),
+        Err(e) => {
+            eprintln!("Couldn't import UltraStar chart: {}", e);
+            None
+        }
+    }
+}
+
+fn is_audio(path: &Path) -> bool {
+    let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+    extension.is_some_and(|e| AUDIO_EXTENSIONS.contains(&e.as_str()))
+}
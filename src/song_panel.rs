@@ -1,55 +1,100 @@
 use cpal::traits::HostTrait;
 use eframe::egui::{self, epaint};
+use std::ops::RangeInclusive;
 use std::sync::Arc;
 use std::time::Duration;
 
 use crate::frame_splitter::FrameSplitter;
 use crate::mic::Microphone;
-use crate::note::Note;
+use crate::note::{self, Note, NoteKind, PitchMatch};
+use crate::preview::Preview;
 use crate::song::{Image, Song};
 use crate::timer::Timer;
 use crate::track::Track;
 
 pub struct TrackSession {
     song: Song,
+    /// Name of the song's track being sung.
+    lead: String,
     mic: Microphone,
     track: Track,
     pub state: State,
     frame_splitter: Option<FrameSplitter>,
+    /// The song's backing audio, which plays until the session is dropped.
+    _audio: Option<Preview>,
     timer: Timer,
     phrase_index: usize,
     note_index: usize,
     chunk_lengths: Vec<u32>,
     chunk_index: usize,
+    lead_in: u32,
+    /// Whether singing a melody an octave off still counts.
+    pitch_match: PitchMatch,
+    /// Milliseconds sung on pitch so far, weighted by note kind.
+    score: u32,
 
     font_id: epaint::text::FontId,
 }
 
+/// How far off a sung note may be, in semitones, and still score.
+const PITCH_TOLERANCE: i32 = 1;
+
 pub enum State {
     Playing,
-    Paused,
     Finished,
 }
 
 impl TrackSession {
-    pub fn new(song: Song) -> Result<TrackSession, std::io::Error> {
-        let backing_track = song.tracks.values().next().unwrap();
-        let initial_note = backing_track.get_phrase(0).unwrap().get(0).unwrap();
-        let initial_note_length = initial_note.length;
-        let video_path = song.video_path.clone();
+    pub fn new(mut song: Song, pitch_match: PitchMatch) -> Result<TrackSession, std::io::Error> {
+        let lead = song
+            .lead_track_name()
+            .ok_or_else(|| std::io::Error::other("song has no tracks"))?
+            .clone();
+        let backing_track = song.tracks.get_mut(&lead).unwrap();
+        // Empty phrases take no time, and there'd be no note to sing in them.
+        backing_track.phrases.retain(|phrase| !phrase.is_empty());
+        if backing_track.phrases.is_empty() {
+            return Err(std::io::Error::other(format!("{} has no notes", lead)));
+        }
+        let backing_track = &song.tracks[&lead];
+        let initial_note_length = backing_track.note_length_ms((0, 0));
+        let lead_in = backing_track.header.gap;
+        let mut mic = Microphone::new(cpal::default_host().default_output_device().expect(""));
+        if lead_in > 0 {
+            mic.set_window_length(Duration::from_millis(lead_in.into()));
+        }
+        let frame_splitter = song
+            .video_path
+            .as_ref()
+            .map(|p| FrameSplitter::new(p))
+            .transpose()?;
+        // Started last so it lines up with the timer.
+        let audio = song
+            .audio_path
+            .as_deref()
+            .and_then(|path| match Preview::play_song(path) {
+                Ok(audio) => Some(audio),
+                Err(e) => {
+                    eprintln!("Couldn't play the song's audio: {}", e);
+                    None
+                }
+            });
         Ok(TrackSession {
             song,
-            mic: Microphone::new(cpal::default_host().default_output_device().expect("")),
+            lead,
+            mic,
             track: Track::new(),
             state: State::Playing,
-            frame_splitter: Some(FrameSplitter::new(
-                &video_path.expect("No video found").to_path_buf(),
-            )?),
+            frame_splitter,
+            _audio: audio,
             timer: Timer::new(),
             phrase_index: 0,
             note_index: 0,
             chunk_lengths: Self::split_into_chunks(initial_note_length),
             chunk_index: 0,
+            lead_in,
+            pitch_match,
+            score: 0,
 
             font_id: epaint::text::FontId {
                 size: 16.0,
@@ -64,7 +109,7 @@ impl TrackSession {
     }
 
     fn next_chunk(&mut self) {
-        let backing_track = self.song.tracks.values().next().unwrap();
+        let backing_track = &self.song.tracks[&self.lead];
         self.chunk_index += 1;
         if self.chunk_index >= self.chunk_lengths.len() {
             self.chunk_index = 0;
@@ -77,7 +122,7 @@ impl TrackSession {
                     return;
                 }
             }
-            let note_length = backing_track.phrases[self.phrase_index][self.note_index].length;
+            let note_length = backing_track.note_length_ms((self.phrase_index, self.note_index));
             self.chunk_lengths = Self::split_into_chunks(note_length);
         }
     }
@@ -85,34 +130,54 @@ impl TrackSession {
     fn ready(&mut self) -> bool {
         let mic_ready = self.mic.ready();
         let remaining = self.timer.time_left_to_process();
-        let chunk_length = self.chunk_lengths[self.chunk_index];
-        return mic_ready && chunk_length <= remaining.as_millis() as u32;
+        let chunk_length = if self.lead_in > 0 {
+            self.lead_in
+        } else {
+            self.chunk_lengths[self.chunk_index]
+        };
+        mic_ready && chunk_length <= remaining.as_millis() as u32
     }
 
+    /// Splits a note into chunks of at most 20 ms. A note with no length
+    /// still gets a chunk of 1 ms so there is something to sing.
     fn split_into_chunks(num: u32) -> Vec<u32> {
+        let num = num.max(1);
         let max_window_length = 20;
-        let k = (num + max_window_length - 1) / max_window_length;
+        let k = num.div_ceil(max_window_length);
         let remainder = num % k;
         let mut vec = vec![num / k; k as usize];
         vec[0] += remainder;
-        return vec;
+        vec
     }
 
     pub fn tick(&mut self) {
-        let backing_track = self.song.tracks.values().next().unwrap().clone();
+        let backing_track = self.song.tracks[&self.lead].clone();
         match self.state {
             State::Playing => {
                 if self.timer.is_paused() {
                     self.timer.resume();
                 }
                 while self.ready() {
+                    if self.lead_in > 0 {
+                        // Nothing is sung before the first note
+                        self.mic.consume();
+                        self.timer
+                            .process(Duration::from_millis(self.lead_in.into()));
+                        self.lead_in = 0;
+                        let chunk_duration =
+                            Duration::from_millis(self.chunk_lengths[self.chunk_index].into());
+                        self.mic.set_window_length(chunk_duration);
+                        continue;
+                    }
                     let current_phrase = backing_track.get_phrase(self.phrase_index);
                     match current_phrase {
                         Some(phrase) => {
                             let current_note = &phrase[self.note_index];
                             let sung_note = self.mic.consume().unwrap();
-                            let difference = current_note.pitch as i32 - sung_note.pitch as i32;
-                            let difference = difference % 12;
+                            let expected =
+                                expected_pitch(current_note, &self.chunk_lengths, self.chunk_index);
+                            self.score +=
+                                points(current_note, expected, &sung_note, self.pitch_match);
                             if self.phrase_index >= self.track.phrases.len() {
                                 self.track.phrases.push(Vec::new());
                             }
@@ -128,45 +193,38 @@ impl TrackSession {
                     }
                 }
             }
-            State::Paused => {
-                self.timer.pause();
-            }
             State::Finished => {}
         }
     }
 
     pub fn draw(&mut self, ui: &mut egui::Ui) -> egui::Response {
-        let backing_track = self.song.tracks.values().next().unwrap();
+        let backing_track = &self.song.tracks[&self.lead];
         let (response, painter) =
             ui.allocate_painter(ui.available_size(), egui::Sense::focusable_noninteractive());
 
-        let to_screen = egui::emath::RectTransform::from_to(
-            egui::Rect::from_min_size(egui::Pos2::ZERO, response.rect.square_proportions()),
-            response.rect,
-        );
-        let from_screen = to_screen.inverse();
         let screen_width = ui.ctx().input().screen_rect().width();
         let screen_height = ui.ctx().input().screen_rect().height();
 
-        let frame_splitter = self.frame_splitter.as_mut().unwrap();
-        let frame = frame_splitter.current_frame(&mut self.timer);
-        let mut video_frame = Image::new();
-        video_frame.load_rgb_image_from_memory(
-            ui.ctx(),
-            frame_splitter.width,
-            frame_splitter.height,
-            frame,
-        );
-        let mut mesh = egui::Mesh::with_texture(video_frame.texture.as_ref().unwrap().id());
-        mesh.add_rect_with_uv(
-            egui::Rect::from_two_pos(
-                egui::pos2(0.0, 0.0),
-                egui::pos2(screen_width, screen_height),
-            ),
-            egui::Rect::from_two_pos(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
-            epaint::Color32::WHITE,
-        );
-        painter.add(egui::Shape::mesh(mesh));
+        if let Some(frame_splitter) = self.frame_splitter.as_mut() {
+            let frame = frame_splitter.current_frame(&mut self.timer);
+            let mut video_frame = Image::new();
+            video_frame.load_rgb_image_from_memory(
+                ui.ctx(),
+                frame_splitter.width,
+                frame_splitter.height,
+                frame,
+            );
+            let mut mesh = egui::Mesh::with_texture(video_frame.texture.as_ref().unwrap().id());
+            mesh.add_rect_with_uv(
+                egui::Rect::from_two_pos(
+                    egui::pos2(0.0, 0.0),
+                    egui::pos2(screen_width, screen_height),
+                ),
+                egui::Rect::from_two_pos(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
+                epaint::Color32::WHITE,
+            );
+            painter.add(egui::Shape::mesh(mesh));
+        }
 
         // ui.image(
         //     video_frame.texture.as_ref().unwrap().id(),
@@ -174,59 +232,74 @@ impl TrackSession {
         // );
 
         let mut shapes = vec![];
-        let backing_phrase = &backing_track.phrases[self.phrase_index];
+        // There's no phrase left once the song has finished.
+        let backing_phrase = match backing_track.phrases.get(self.phrase_index) {
+            Some(phrase) => &phrase[..],
+            None => &[],
+        };
+        let pitches = pitch_range(backing_phrase);
+        let mut position = match backing_phrase {
+            [] => 0,
+            _ => backing_track.note_position((self.phrase_index, 0)),
+        };
         let mut length = 0;
 
         let backing_stroke = epaint::Stroke::new(4.0, epaint::color::Color32::GRAY);
+        let golden_stroke = epaint::Stroke::new(4.0, epaint::color::Color32::GOLD);
+        let freestyle_stroke = epaint::Stroke::new(4.0, epaint::color::Color32::DARK_GREEN);
+        let rap_stroke = epaint::Stroke::new(4.0, epaint::color::Color32::LIGHT_RED);
         let rest_stroke = epaint::Stroke::new(4.0, epaint::color::Color32::WHITE);
         let player_stroke = epaint::Stroke::new(4.0, epaint::color::Color32::BLUE);
 
         for (index, note) in backing_phrase.iter().enumerate() {
-            let path = note_path(note.clone(), length)
+            let mut note = note.clone();
+            note.length = backing_track.header.length_to_ms(position, note.length);
+            position += backing_phrase[index].length;
+            let path = note_path(note.clone(), length, &pitches)
                 .iter()
                 .map(|(x, y)| egui::Pos2 { x: *x, y: *y })
                 .collect();
 
             length += note.length;
-            if note.voiced {
-                shapes.push(egui::Shape::line(path, backing_stroke));
-            } else {
-                shapes.push(egui::Shape::line(path, rest_stroke));
-            }
+            let stroke = match (note.voiced, note.kind) {
+                (false, _) => rest_stroke,
+                (true, NoteKind::Normal) => backing_stroke,
+                (true, NoteKind::Golden) => golden_stroke,
+                (true, NoteKind::Freestyle) => freestyle_stroke,
+                (true, NoteKind::Rap) => rap_stroke,
+            };
+            shapes.push(egui::Shape::line(path, stroke));
         }
 
-        let singing_phrase = &self.track.phrases.get(self.phrase_index);
-        match singing_phrase {
-            Some(phrase) => {
-                let mut length = 0;
-                for note in *phrase {
-                    let path = note_path(note.clone(), length)
-                        .iter()
-                        .map(|(x, y)| egui::Pos2 { x: *x, y: *y })
-                        .collect();
-                    length += note.length;
-                    if note.voiced {
-                        shapes.push(egui::Shape::line(path, player_stroke));
-                    } else {
-                        shapes.push(egui::Shape::line(path, rest_stroke));
-                    }
+        if let Some(phrase) = self.track.phrases.get(self.phrase_index) {
+            let mut length = 0;
+            for note in phrase {
+                let path = note_path(note.clone(), length, &pitches)
+                    .iter()
+                    .map(|(x, y)| egui::Pos2 { x: *x, y: *y })
+                    .collect();
+                length += note.length;
+                if note.voiced {
+                    shapes.push(egui::Shape::line(path, player_stroke));
+                } else {
+                    shapes.push(egui::Shape::line(path, rest_stroke));
                 }
             }
-            None => (),
         }
 
         let mut lyrics: Vec<Arc<epaint::text::Galley>> = vec![];
 
         for (i, note) in backing_phrase.iter().enumerate() {
+            let text = note.lyric.clone() + note::lyric_separator(&backing_phrase[i..]);
             if i == self.note_index {
                 lyrics.push(ui.fonts().layout_no_wrap(
-                    note.lyric.clone() + " ",
+                    text,
                     self.font_id.clone(),
                     epaint::color::Color32::BLUE,
                 ));
             } else {
                 lyrics.push(ui.fonts().layout_no_wrap(
-                    note.lyric.clone() + " ",
+                    text,
                     self.font_id.clone(),
                     epaint::color::Color32::WHITE,
                 ));
@@ -236,7 +309,7 @@ impl TrackSession {
         let lyric_widths: Vec<f32> = lyrics.iter().map(|lyric| lyric.size().x).collect();
         let total_width = lyric_widths.iter().fold(0.0, |x, y| x + y);
         let mut current_x = (ui.available_width() - total_width) / 2.0;
-        let current_y = lyrics.get(0).unwrap().size().y / 2.0 + 10.0;
+        let current_y = ui.fonts().row_height(&self.font_id) / 2.0 + 10.0;
         for lyric in lyrics {
             shapes.push(egui::Shape::Text(epaint::TextShape {
                 pos: egui::Pos2 {
@@ -251,20 +324,125 @@ impl TrackSession {
             current_x += lyric.size().x;
         }
 
+        let score = ui.fonts().layout_no_wrap(
+            format!("Score: {}", self.score),
+            self.font_id.clone(),
+            epaint::color::Color32::WHITE,
+        );
+        shapes.push(egui::Shape::Text(epaint::TextShape {
+            pos: egui::Pos2 {
+                x: ui.available_width() - score.size().x - 10.0,
+                y: current_y,
+            },
+            galley: score,
+            underline: epaint::Stroke::default(),
+            override_text_color: None,
+            angle: 0.0,
+        }));
+
         painter.extend(shapes);
         response
     }
 }
 
-fn note_path(note: Note, length: u32) -> Vec<(f32, f32)> {
-    let y = (((note.pitch % 12) + 12) % 12) as f32;
+/// Pitch `note` should have at the middle of chunk `chunk_index`, which
+/// changes over the note when it slides.
+fn expected_pitch(note: &Note, chunk_lengths: &[u32], chunk_index: usize) -> i8 {
+    let note_length: u32 = chunk_lengths.iter().sum();
+    let before: u32 = chunk_lengths[..chunk_index].iter().sum();
+    let middle = before as f32 + chunk_lengths[chunk_index] as f32 / 2.0;
+    note.pitch_at(middle / note_length.max(1) as f32).round() as i8
+}
+
+/// Points for singing `sung` over a chunk of `note` that should be at
+/// `expected`: the chunk's length, weighted by the note's kind, if it was
+/// sung on pitch. Rests and unvoiced singing score nothing.
+fn points(note: &Note, expected: i8, sung: &Note, pitch_match: PitchMatch) -> u32 {
+    if !note.voiced || !sung.voiced {
+        return 0;
+    }
+    let difference = pitch_match.difference(expected, sung.pitch);
+    if note.kind.scores_pitch() && difference.abs() > PITCH_TOLERANCE {
+        return 0;
+    }
+    sung.length * note.kind.weight()
+}
+
+/// Pitches shown for a phrase: from its lowest to its highest voiced note,
+/// with a few semitones of room on either side.
+fn pitch_range(phrase: &[Note]) -> RangeInclusive<i8> {
+    let voiced = phrase
+        .iter()
+        .filter(|note| note.voiced)
+        .flat_map(|note| [note.pitch, note.end_pitch()]);
+    let low = voiced.clone().min().unwrap_or(note::A4);
+    let high = voiced.max().unwrap_or(note::A4);
+    low.saturating_sub(3)..=high.saturating_add(3)
+}
+
+/// Notes outside `pitches`, such as a sung note an octave off, are drawn at
+/// the nearest edge. Slides are drawn sloping to their end pitch.
+fn note_path(note: Note, length: u32, pitches: &RangeInclusive<i8>) -> Vec<(f32, f32)> {
+    let y = |pitch: i8| (*pitches.end() - pitch.clamp(*pitches.start(), *pitches.end())) as f32;
     let x = length as f32;
-    let mut note_path = vec![];
-    note_path.push(note_to_frame_transform((x, y)));
-    note_path.push(note_to_frame_transform((x + (note.length as f32), y)));
-    note_path
+    vec![
+        note_to_frame_transform((x, y(note.pitch))),
+        note_to_frame_transform((x + (note.length as f32), y(note.end_pitch()))),
+    ]
 }
 
 fn note_to_frame_transform((x, y): (f32, f32)) -> (f32, f32) {
-    (x * 0.5, 100.0 - 5.0 * y)
+    (x * 0.5, 40.0 + 5.0 * y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sung(pitch: i8) -> Note {
+        Note::new(20, pitch, true, String::new())
+    }
+
+    #[test]
+    fn scores_by_note_kind() {
+        let note = |kind| Note::new(100, 60, true, String::new()).with_kind(kind);
+        let score = |kind, sung: Note| points(&note(kind), 60, &sung, PitchMatch::Exact);
+        assert_eq!(score(NoteKind::Normal, sung(61)), 20);
+        assert_eq!(score(NoteKind::Normal, sung(62)), 0);
+        assert_eq!(score(NoteKind::Golden, sung(60)), 40);
+        assert_eq!(score(NoteKind::Golden, sung(66)), 0);
+        assert_eq!(score(NoteKind::Freestyle, sung(60)), 0);
+        assert_eq!(score(NoteKind::Rap, sung(60)), 20);
+        assert_eq!(score(NoteKind::Rap, sung(40)), 20);
+        let silence = Note::new(20, 60, false, String::new());
+        assert_eq!(score(NoteKind::Rap, silence), 0);
+        let rest = Note::new(100, 60, false, String::new());
+        assert_eq!(points(&rest, 60, &sung(60), PitchMatch::Exact), 0);
+    }
+
+    #[test]
+    fn splits_notes_into_chunks() {
+        assert_eq!(TrackSession::split_into_chunks(50), [18, 16, 16]);
+        assert_eq!(TrackSession::split_into_chunks(20), [20]);
+        assert_eq!(TrackSession::split_into_chunks(0), [1]);
+    }
+
+    #[test]
+    fn follows_slides_mid_note() {
+        let note = Note::new(100, 60, true, String::new()).with_slide(Some(72));
+        let chunks = TrackSession::split_into_chunks(100);
+        assert_eq!(chunks, [20; 5]);
+        assert_eq!(expected_pitch(&note, &chunks, 0), 61);
+        assert_eq!(expected_pitch(&note, &chunks, 2), 66);
+        assert_eq!(expected_pitch(&note, &chunks, 4), 71);
+        let held = Note::new(100, 60, true, String::new());
+        assert_eq!(expected_pitch(&held, &chunks, 4), 60);
+    }
+
+    #[test]
+    fn scores_any_octave() {
+        let note = Note::new(100, 60, true, String::new());
+        assert_eq!(points(&note, 60, &sung(48), PitchMatch::Exact), 0);
+        assert_eq!(points(&note, 60, &sung(48), PitchMatch::AnyOctave), 20);
+    }
 }
@@ -1,21 +1,227 @@
-use crate::note::Note;
-use crate::track::SelectMode;
-use crate::track::Track;
+use crate::history::History;
+use crate::manifest::Manifest;
+use crate::note::{self, Note, NoteKind};
+use crate::preview::Preview;
+use crate::recorder::Recorder;
+use crate::song_library::SongLibrary;
+use crate::syllables::Language;
+use crate::track::{Clip, NoteIndex, SelectMode, Track};
+use crate::track_file::{Resolution, TrackError};
 use cursive::{
     event::{Event, EventResult, Key},
     theme::{BaseColor, ColorStyle},
-    traits::Resizable,
-    views, Printer, Rect, XY,
+    traits::{Nameable, Resizable},
+    views, Cursive, Printer, Rect, XY,
 };
-use std::path::Path;
+use std::cmp;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+/// Audio plays from this many milliseconds before a phrase being synced, so
+/// there is time to catch the beat.
+const SYNC_LEAD_IN: u32 = 3000;
 
 pub struct TrackView {
     track: Track,
+    /// Where the track is saved to.
+    path: PathBuf,
+    /// Whether there are changes since the track was last saved.
+    dirty: bool,
+    history: History<Track>,
+    clipboard: Option<Clip>,
+    preview: Option<Preview>,
+    recorder: Option<Recorder>,
+    /// The song's audio, for syncing notes to.
+    audio: Option<PathBuf>,
+    sync: Option<Sync>,
+    /// How pasted lyrics are split into syllables.
+    language: Language,
+}
+
+/// Tapping along to the song's audio to time the notes of a phrase.
+struct Sync {
+    phrase: usize,
+    /// How many voiced notes there are to tap.
+    notes: usize,
+    /// When each note was tapped and, if it was, let go of, in milliseconds
+    /// from the start of the audio.
+    taps: Vec<(u32, Option<u32>)>,
 }
 
 impl TrackView {
-    pub fn new(track: Track) -> TrackView {
-        TrackView { track }
+    pub fn new(track: Track, path: PathBuf) -> TrackView {
+        TrackView {
+            track,
+            path,
+            dirty: false,
+            history: History::new(),
+            clipboard: None,
+            preview: None,
+            recorder: None,
+            audio: None,
+            sync: None,
+            language: Language::English,
+        }
+    }
+
+    /// Plays the notes in `range` as tones.
+    fn play(&mut self, range: RangeInclusive<NoteIndex>) -> EventResult {
+        match Preview::play(&self.track, range) {
+            Ok(preview) => {
+                self.preview = preview;
+                EventResult::Consumed(None)
+            }
+            Err(e) => {
+                let message = format!("Couldn't play: {}", e);
+                EventResult::with_cb(move |s| s.add_layer(views::Dialog::info(&message)))
+            }
+        }
+    }
+
+    /// Starts recording from the microphone, or stops and inserts what was
+    /// sung before the selection.
+    fn toggle_recording(&mut self) -> EventResult {
+        match self.recorder.take() {
+            Some(recorder) => {
+                let notes = recorder.finish();
+                self.history.record(&self.track, "record");
+                self.history.seal();
+                self.track.insert_timed(notes);
+                self.dirty = true;
+            }
+            None => {
+                self.recorder = Recorder::start();
+                if self.recorder.is_none() {
+                    return EventResult::with_cb(|s| {
+                        s.add_layer(views::Dialog::info("No microphone found"))
+                    });
+                }
+            }
+        }
+        EventResult::Consumed(None)
+    }
+
+    /// Starts playing the song's audio from a little before the selected
+    /// phrase, to tap along and time its notes.
+    fn start_sync(&mut self) -> EventResult {
+        let audio = match &self.audio {
+            Some(audio) => audio,
+            None => {
+                return EventResult::with_cb(|s| {
+                    s.add_layer(views::Dialog::info("No audio found for this track"))
+                })
+            }
+        };
+        let p = self.track.select_begin.0;
+        let notes = match self.track.phrases.get(p) {
+            Some(phrase) => phrase.iter().filter(|note| note.voiced).count(),
+            None => 0,
+        };
+        if notes == 0 {
+            return EventResult::Consumed(None);
+        }
+        let start = self.track.note_time((p, 0)).saturating_sub(SYNC_LEAD_IN);
+        match Preview::play_audio(audio, start) {
+            Ok(preview) => {
+                self.preview = Some(preview);
+                self.sync = Some(Sync {
+                    phrase: p,
+                    notes,
+                    taps: vec![],
+                });
+                EventResult::Consumed(None)
+            }
+            Err(e) => {
+                let message = format!("Couldn't play: {}", e);
+                EventResult::with_cb(move |s| s.add_layer(views::Dialog::info(&message)))
+            }
+        }
+    }
+
+    /// Keys while syncing: space taps the next note, enter lets go of it,
+    /// `T` finishes early and escape cancels. Syncing finishes by itself
+    /// when the last note is let go of or the audio ends.
+    fn handle_sync_event(&mut self, event: Event) -> EventResult {
+        let now = self.preview.as_ref().and_then(Preview::position);
+        let (sync, now) = match (&mut self.sync, now) {
+            (Some(sync), Some(now)) => (sync, now),
+            _ => return self.finish_sync(),
+        };
+        match event {
+            Event::Char(' ') if sync.taps.len() < sync.notes => {
+                sync.taps.push((now, None));
+            }
+            Event::Key(Key::Enter) => {
+                if let Some((_, release)) = sync.taps.last_mut() {
+                    release.get_or_insert(now);
+                }
+                if sync.taps.len() == sync.notes {
+                    return self.finish_sync();
+                }
+            }
+            Event::Char('T') => {
+                return self.finish_sync();
+            }
+            Event::Key(Key::Esc) => {
+                self.sync = None;
+                self.preview = None;
+            }
+            _ => return EventResult::Ignored,
+        }
+        EventResult::Consumed(None)
+    }
+
+    /// Stops syncing and times the phrase to the taps so far.
+    fn finish_sync(&mut self) -> EventResult {
+        self.preview = None;
+        if let Some(sync) = self.sync.take().filter(|sync| !sync.taps.is_empty()) {
+            self.history.record(&self.track, "sync");
+            self.history.seal();
+            self.track.retime_phrase(sync.phrase, &sync.taps);
+            self.dirty = true;
+        }
+        EventResult::Consumed(None)
+    }
+
+    fn status(&self) -> String {
+        let modified = if self.dirty { " [modified]" } else { "" };
+        let recording = if self.recorder.is_some() {
+            " [recording]"
+        } else {
+            ""
+        };
+        let syncing = match &self.sync {
+            Some(sync) => format!(" [syncing {}/{}]", sync.taps.len(), sync.notes),
+            None => String::new(),
+        };
+        let (p, n) = self.track.select_begin;
+        let pitch = match self.track.phrases.get(p).and_then(|phrase| phrase.get(n)) {
+            Some(note) if note.voiced => match note.slide_to {
+                Some(end) => format!(
+                    " {}>{}",
+                    note::pitch_name(note.pitch),
+                    note::pitch_name(end)
+                ),
+                None => format!(" {}", note::pitch_name(note.pitch)),
+            },
+            _ => String::new(),
+        };
+        format!(
+            "{}{}{}{}{}",
+            self.path.display(),
+            modified,
+            recording,
+            syncing,
+            pitch
+        )
+    }
+
+    /// Row where phrase `p` starts.
+    fn phrase_top(&self, p: usize) -> usize {
+        self.track.phrases[..p]
+            .iter()
+            .map(|phrase| pitch_rows(phrase).1)
+            .sum()
     }
 }
 
@@ -27,22 +233,32 @@ impl cursive::view::View for TrackView {
         let voice_color_focus = ColorStyle::new(BaseColor::White.dark(), BaseColor::Blue.dark());
         let rest_color = ColorStyle::new(BaseColor::White.dark(), BaseColor::Magenta.dark());
         let rest_color_focus = ColorStyle::new(BaseColor::White.dark(), BaseColor::Magenta.dark());
+        let golden_color = ColorStyle::new(BaseColor::Black.dark(), BaseColor::Yellow.dark());
+        let freestyle_color = ColorStyle::new(BaseColor::White.dark(), BaseColor::Green.dark());
+        let rap_color = ColorStyle::new(BaseColor::White.dark(), BaseColor::Cyan.dark());
         let track = &self.track;
+        // Lengths are drawn one column each, so a beat is as wide as its ticks.
+        let beat_width = match track.header.resolution {
+            Resolution::Ticks { ticks_per_beat } => ticks_per_beat as usize,
+            Resolution::Milliseconds => 8,
+        };
+        let beats_per_bar = track.header.time_signature.beats_per_bar as usize;
 
         for p in 0..track.phrases.len() {
             let phrase = &track.phrases[p];
+            let (top, rows) = pitch_rows(phrase);
             let mut x: u32 = 0;
 
             //draw measure markers
             for x in 0..20 {
-                if x % 4 == 0 {
+                if x % beats_per_bar == 0 {
                     //TODO combine this into one
                     printer.with_color(rest_color, |printer| {
-                        printer.print_vline((8 * x, 12 * p), 12, " ")
+                        printer.print_vline((beat_width * x, y as usize), rows, " ")
                     });
                 } else {
                     printer.with_color(voice_color, |printer| {
-                        printer.print_vline((8 * x, 12 * p), 12, " ")
+                        printer.print_vline((beat_width * x, y as usize), rows, " ")
                     });
                 }
             }
@@ -50,10 +266,10 @@ impl cursive::view::View for TrackView {
             //draw notes
             for n in 0..phrase.len() {
                 let note = &phrase[n];
-                let note_y = y + (11 - note.pitch % 12) as u32;
+                let note_y = y + (top - note.pitch as i32) as u32;
                 if track.in_selection((p, n)) {
                     printer.with_color(bg_color, |printer| {
-                        for y in y..(y + 12) {
+                        for y in y..(y + rows as u32) {
                             printer.print(
                                 (x, y),
                                 &String::from(" ").repeat(note.length.try_into().unwrap()),
@@ -61,28 +277,52 @@ impl cursive::view::View for TrackView {
                         }
                     });
                 }
-                let color = if track.in_selection((p, n)) {
-                    if note.voiced {
-                        voice_color_focus
-                    } else {
+                let color = if !note.voiced {
+                    if track.in_selection((p, n)) {
                         rest_color_focus
-                    }
-                } else {
-                    if note.voiced {
-                        voice_color
                     } else {
                         rest_color
                     }
+                } else {
+                    match note.kind {
+                        NoteKind::Normal if track.in_selection((p, n)) => voice_color_focus,
+                        NoteKind::Normal => voice_color,
+                        NoteKind::Golden => golden_color,
+                        NoteKind::Freestyle => freestyle_color,
+                        NoteKind::Rap => rap_color,
+                    }
                 };
                 printer.with_color(color, |printer| {
-                    let lyrics = note.lyric.to_string();
+                    let mut lyrics = note.lyric.to_string();
+                    if note::joins_next(&phrase[n..]) {
+                        lyrics += "-";
+                    }
                     let length = note.length.try_into().unwrap();
                     let lyrics = pad_to_width(lyrics, length);
-                    printer.print((x, note_y), &lyrics)
+                    printer.print((x, note_y), &lyrics);
+                    // Slides show where they end with a mark in their last column.
+                    if let Some(end) = note.slide_to {
+                        let end_y = y + (top - end as i32) as u32;
+                        let mark = if end > note.pitch { "/" } else { "\\" };
+                        printer.print((x + note.length.saturating_sub(1), end_y), mark);
+                    }
                 });
                 x += note.length;
             }
-            y += 12;
+            y += rows as u32;
+        }
+
+        // The playhead goes through the phrase being played.
+        let playing = self.preview.as_ref().and_then(Preview::position);
+        if let Some((ms, (p, n))) = playing.and_then(|ms| Some((ms, track.note_at_time(ms)?))) {
+            let phrase = &track.phrases[p];
+            let before: u32 = phrase[..n].iter().map(|note| note.length).sum();
+            let elapsed = ms.saturating_sub(track.note_time((p, n)));
+            let fraction = elapsed as f32 / track.note_length_ms((p, n)).max(1) as f32;
+            let x = before + (fraction * phrase[n].length as f32) as u32;
+            printer.with_color(bg_color, |printer| {
+                printer.print_vline((x as usize, self.phrase_top(p)), pitch_rows(phrase).1, "|")
+            });
         }
     }
 
@@ -91,16 +331,94 @@ impl cursive::view::View for TrackView {
         let selection = track.get_selection_bounds();
         let start = selection.0;
         let end = selection.1;
-        let corner1 = (start.1 * 8, start.0 * 12);
-        let corner2 = ((end.1 - 1) * 8 + 1, end.0 * 12);
+        let corner1 = (start.1 * 8, self.phrase_top(start.0));
+        let bottom = cmp::min(end.0 + 1, track.phrases.len());
+        let corner2 = (end.1 * 8 + 1, self.phrase_top(bottom));
         Rect::from_corners(corner1, corner2)
     }
 
     fn on_event(&mut self, event: Event) -> EventResult {
+        let result = self.handle_event(event);
+        if result.is_consumed() {
+            result.and(EventResult::with_cb(update_status))
+        } else {
+            result
+        }
+    }
+
+    fn required_size(&mut self, _constraint: XY<usize>) -> XY<usize> {
+        XY::new(200, self.phrase_top(self.track.phrases.len()))
+    }
+}
+
+impl TrackView {
+    fn handle_event(&mut self, event: Event) -> EventResult {
+        match event {
+            Event::Refresh => {
+                if let Some(recorder) = &mut self.recorder {
+                    recorder.poll();
+                }
+                let playing = self.preview.as_ref().is_some_and(Preview::is_playing);
+                if self.sync.is_some() && !playing {
+                    return self.finish_sync();
+                }
+                return EventResult::Ignored;
+            }
+            _ if self.sync.is_some() => {
+                return self.handle_sync_event(event);
+            }
+            Event::CtrlChar('z') | Event::Char('u') => {
+                self.dirty |= self.history.undo(&mut self.track).is_some();
+                return EventResult::Consumed(None);
+            }
+            Event::CtrlChar('y') | Event::CtrlChar('r') => {
+                self.dirty |= self.history.redo(&mut self.track).is_some();
+                return EventResult::Consumed(None);
+            }
+            _ => {}
+        }
+        let kind = edit_kind(&event);
+        if kind.is_none() {
+            self.history.seal();
+        }
+        let before = kind.map(|_| self.track.clone());
+        let result = self.apply_key(event);
+        // Edits that change nothing, like pasting an empty clipboard, leave
+        // no undo step and no unsaved changes.
+        if let (Some(kind), Some(before)) = (kind, before) {
+            if !same_notes(&before, &self.track) {
+                self.history.record(&before, kind);
+                self.dirty = true;
+            }
+        }
+        result
+    }
+
+    /// Applies a key that isn't undo, redo or part of syncing.
+    fn apply_key(&mut self, event: Event) -> EventResult {
         let track = &mut self.track;
         match event {
             Event::CtrlChar('s') => {
-                track.write(Path::new("test.song"));
+                return EventResult::with_cb(|s| {
+                    save(s, None);
+                });
+            }
+            Event::Char('S') => {
+                let path = self.path.display().to_string();
+                return EventResult::with_cb(move |s| {
+                    s.add_layer(
+                        views::Dialog::around(
+                            views::EditView::new()
+                                .content(path.clone())
+                                .on_submit(|s, path| {
+                                    s.pop_layer();
+                                    save(s, Some(PathBuf::from(path)));
+                                })
+                                .fixed_width(40),
+                        )
+                        .title("Save as"),
+                    )
+                });
             }
             Event::Key(Key::Left) => {
                 track.select_prev(1);
@@ -140,6 +458,12 @@ impl cursive::view::View for TrackView {
             Event::Char(']') => {
                 track.change_pitch(1);
             }
+            Event::Char('{') => {
+                track.change_slide(-1);
+            }
+            Event::Char('}') => {
+                track.change_slide(1);
+            }
             Event::Char('n') => {
                 track.resize_note(-1);
             }
@@ -149,6 +473,70 @@ impl cursive::view::View for TrackView {
             Event::Char('t') => {
                 track.toggle_voiced();
             }
+            Event::Char('k') => {
+                track.cycle_kind();
+            }
+            Event::Char('j') => {
+                track.toggle_continues();
+            }
+            Event::Char('c') => {
+                self.clipboard = Some(track.copy_selection());
+            }
+            Event::Char('x') => {
+                self.clipboard = Some(track.cut_selection());
+            }
+            Event::Char('p') => {
+                if let Some(clip) = &self.clipboard {
+                    track.paste_after(clip);
+                }
+            }
+            Event::Char('P') => {
+                if let Some(clip) = &self.clipboard {
+                    track.paste_before(clip);
+                }
+            }
+            Event::Char('d') => {
+                track.duplicate_selection();
+            }
+            Event::Char('s') => {
+                let (p, n) = track.select_begin;
+                let note = track.phrases.get(p).and_then(|phrase| phrase.get(n));
+                if let Some(length) = note.map(|note| note.length) {
+                    track.split_note(length / 2);
+                }
+            }
+            Event::Char('g') => {
+                track.merge_notes();
+            }
+            Event::Char('b') => {
+                track.break_phrase();
+            }
+            Event::Char('J') => {
+                track.join_phrases();
+            }
+            Event::Key(Key::Del) => {
+                track.delete_selection();
+            }
+            Event::Char(' ') if self.preview.as_ref().is_some_and(Preview::is_playing) => {
+                self.preview = None;
+            }
+            Event::Char(' ') => {
+                let range = track.selected_notes();
+                return self.play(range);
+            }
+            Event::Char('o') => {
+                let start = *track.selected_notes().start();
+                return self.play(start..=(usize::MAX, usize::MAX));
+            }
+            Event::Char('O') => {
+                return self.play((0, 0)..=(usize::MAX, usize::MAX));
+            }
+            Event::Char('r') => {
+                return self.toggle_recording();
+            }
+            Event::Char('T') => {
+                return self.start_sync();
+            }
             Event::Char('v') => {
                 track.toggle_selection_mode();
             }
@@ -158,20 +546,79 @@ impl cursive::view::View for TrackView {
                         views::EditView::new()
                             .on_submit(|s, l| {
                                 s.call_on_name("view", |v: &mut TrackView| {
-                                    v.track.change_lyrics(&l);
+                                    v.history.record(&v.track, "change lyrics");
+                                    v.history.seal();
+                                    v.track.change_lyrics(l);
+                                    v.dirty = true;
                                 });
                                 s.pop_layer();
+                                update_status(s);
                             })
                             .fixed_width(20),
                     )
                 })
             }
+            Event::Char('=') => {
+                return EventResult::with_cb(|s| {
+                    s.add_layer(
+                        views::Dialog::around(
+                            views::EditView::new()
+                                .on_submit(|s, name| {
+                                    let pitch = match note::parse_pitch_name(name) {
+                                        Some(pitch) => pitch,
+                                        None => {
+                                            let message = format!("Not a pitch: {}", name);
+                                            s.add_layer(views::Dialog::info(message));
+                                            return;
+                                        }
+                                    };
+                                    s.call_on_name("view", |v: &mut TrackView| {
+                                        v.history.record(&v.track, "set pitch");
+                                        v.history.seal();
+                                        v.track.set_pitch(pitch);
+                                        v.dirty = true;
+                                    });
+                                    s.pop_layer();
+                                    update_status(s);
+                                })
+                                .fixed_width(10),
+                        )
+                        .title("Pitch, like C#4"),
+                    )
+                })
+            }
+            Event::Char('L') => {
+                return EventResult::with_cb(|s| {
+                    s.add_layer(
+                        views::Dialog::around(
+                            views::TextArea::new().with_name("lyrics").min_size((40, 8)),
+                        )
+                        .title("Paste lyrics")
+                        .button("Paste", |s| {
+                            let lyrics = s
+                                .call_on_name("lyrics", |t: &mut views::TextArea| {
+                                    t.get_content().to_string()
+                                })
+                                .unwrap_or_default();
+                            s.call_on_name("view", |v: &mut TrackView| {
+                                v.history.record(&v.track, "paste lyrics");
+                                v.history.seal();
+                                v.track.paste_lyrics(&lyrics, v.language);
+                                v.dirty = true;
+                            });
+                            s.pop_layer();
+                            update_status(s);
+                        })
+                        .dismiss_button("Cancel"),
+                    )
+                })
+            }
             Event::Char('a') => {
-                let note = Note::new(8, 70, true, "".to_string());
+                let note = Note::new(note::DEFAULT_LENGTH, note::A4, true, "".to_string());
                 track.add_after(note);
             }
             Event::Char('i') => {
-                let note = Note::new(8, 70, true, "".to_string());
+                let note = Note::new(note::DEFAULT_LENGTH, note::A4, true, "".to_string());
                 track.add_before(note);
             }
             _ => {
@@ -180,17 +627,213 @@ impl cursive::view::View for TrackView {
         }
         EventResult::Consumed(None)
     }
+}
 
-    fn required_size(&mut self, _constraint: XY<usize>) -> XY<usize> {
-        return XY::new(200, 12 * self.track.phrases.len());
+/// Opens the track at `path` in the terminal editor, or starts a new track
+/// there if the file doesn't exist yet.
+pub fn edit(path: &Path) -> Result<(), TrackError> {
+    let track = if path.exists() {
+        Track::read(path)?
+    } else {
+        let mut track = Track::new();
+        if let Some(name) = path.file_stem() {
+            track.name = name.to_string_lossy().into_owned();
+        }
+        track
+    };
+    let mut view = TrackView::new(track, path.to_path_buf());
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+    let dir = dir.unwrap_or(Path::new("."));
+    view.audio = SongLibrary::find_audio(dir);
+    if let Ok(Some(Manifest {
+        language: Some(language),
+        ..
+    })) = Manifest::read_dir(dir)
+    {
+        view.language = Language::from_name(&language);
     }
+    let status = view.status();
+    let mut siv = cursive::default();
+    // Keeps the playhead moving and the microphone read while recording.
+    siv.set_autorefresh(true);
+    siv.add_global_callback('q', quit);
+    siv.clear_global_callbacks(Event::CtrlChar('c'));
+    siv.add_global_callback(Event::CtrlChar('c'), quit);
+    siv.add_fullscreen_layer(
+        views::LinearLayout::vertical()
+            .child(
+                views::ScrollView::new(view.with_name("view"))
+                    .scroll_x(true)
+                    .full_screen(),
+            )
+            .child(views::TextView::new(status).with_name("status")),
+    );
+    siv.run();
+    Ok(())
+}
+
+/// Shows which file is being edited and whether it has unsaved changes.
+fn update_status(s: &mut Cursive) {
+    if let Some(status) = s.call_on_name("view", |v: &mut TrackView| v.status()) {
+        s.call_on_name("status", |t: &mut views::TextView| t.set_content(status));
+    }
+}
+
+/// Saves the track to `path`, or to where it was last saved, and shows a
+/// dialog if that fails. Returns whether it was saved.
+fn save(s: &mut Cursive, path: Option<PathBuf>) -> bool {
+    let result = s.call_on_name("view", |v: &mut TrackView| {
+        let path = path.unwrap_or_else(|| v.path.clone());
+        v.track.write(&path)?;
+        v.path = path;
+        v.dirty = false;
+        Ok::<_, TrackError>(())
+    });
+    update_status(s);
+    match result {
+        Some(Ok(())) => true,
+        Some(Err(e)) => {
+            s.add_layer(views::Dialog::info(format!("Couldn't save: {}", e)));
+            false
+        }
+        None => false,
+    }
+}
+
+/// Quits, asking first whether to save any unsaved changes.
+fn quit(s: &mut Cursive) {
+    let dirty = s.call_on_name("view", |v: &mut TrackView| v.dirty);
+    if dirty != Some(true) {
+        s.quit();
+        return;
+    }
+    s.add_layer(
+        views::Dialog::text("Save changes before quitting?")
+            .button("Save", |s| {
+                if save(s, None) {
+                    s.quit();
+                }
+            })
+            .button("Don't save", |s| s.quit())
+            .dismiss_button("Cancel"),
+    );
 }
 
+/// Every phrase is drawn one row per semitone, over whole octaves from C to B
+/// that cover all of its notes. Returns the pitch of the top row and the
+/// number of rows.
+fn pitch_rows(phrase: &[Note]) -> (i32, usize) {
+    let pitches = phrase
+        .iter()
+        .flat_map(|note| [note.pitch, note.end_pitch()]);
+    let low = pitches.clone().min().unwrap_or(note::A4);
+    let high = pitches.max().unwrap_or(note::A4);
+    let low = low as i32 - (low as i32).rem_euclid(12);
+    let high = high as i32 - (high as i32).rem_euclid(12) + 11;
+    (high, (high - low + 1) as usize)
+}
+
+/// Whether two versions of a track have the same name, header and notes,
+/// whatever is selected.
+fn same_notes(a: &Track, b: &Track) -> bool {
+    a.name == b.name && a.header == b.header && a.phrases == b.phrases
+}
+
+/// Which kind of edit a key makes to the track, for the undo history, or
+/// `None` if it doesn't change the track.
+fn edit_kind(event: &Event) -> Option<&'static str> {
+    match event {
+        Event::Char('[') | Event::Char(']') => Some("change pitch"),
+        Event::Char('{') | Event::Char('}') => Some("change slide"),
+        Event::Char('n') | Event::Char('m') => Some("resize"),
+        Event::Char('t') => Some("toggle voiced"),
+        Event::Char('k') => Some("change kind"),
+        Event::Char('j') => Some("toggle continues"),
+        Event::Char('a') | Event::Char('i') => Some("insert note"),
+        Event::Char('x') => Some("cut"),
+        Event::Char('p') | Event::Char('P') => Some("paste"),
+        Event::Char('d') => Some("duplicate"),
+        Event::Char('s') => Some("split"),
+        Event::Char('g') => Some("merge"),
+        Event::Char('b') => Some("break phrase"),
+        Event::Char('J') => Some("join phrases"),
+        Event::Key(Key::Del) => Some("delete"),
+        _ => None,
+    }
+}
+
+/// Cuts `s` to `width` characters, or pads it with spaces to that width.
 fn pad_to_width(s: String, width: usize) -> String {
-    if s.len() > width {
-        s[..width].to_string()
+    let chars = s.chars().count();
+    if chars > width {
+        s.chars().take(width).collect()
     } else {
-        let tail = &String::from(" ").repeat(width - s.len());
+        let tail = &String::from(" ").repeat(width - chars);
         s + tail
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn pads_by_characters() {
+        assert_eq!(pad_to_width("la".to_string(), 4), "la  ");
+        assert_eq!(pad_to_width("ça va".to_string(), 3), "ça ");
+        assert_eq!(pad_to_width("日本語".to_string(), 2), "日本");
+        assert_eq!(pad_to_width("über".to_string(), 5), "über ");
+    }
+
+    #[test]
+    fn saves_and_tracks_changes() {
+        let dir = std::env::temp_dir().join(format!("karaoke-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (first, second) = (dir.join("first.track"), dir.join("second.track"));
+        let mut track = Track::new();
+        track.name = "Song".to_string();
+        track.phrases = vec![vec![Note::new(8, 60, true, "la".to_string())]];
+
+        let mut siv = Cursive::new();
+        siv.add_layer(TrackView::new(track, first.clone()).with_name("view"));
+        let dirty = |siv: &mut Cursive| siv.call_on_name("view", |v: &mut TrackView| v.dirty);
+        let edit = |siv: &mut Cursive, key| {
+            siv.call_on_name("view", |v: &mut TrackView| v.handle_event(Event::Char(key)));
+        };
+        assert_eq!(dirty(&mut siv), Some(false));
+        // Pasting with nothing copied changes nothing.
+        edit(&mut siv, 'p');
+        assert_eq!(dirty(&mut siv), Some(false));
+        edit(&mut siv, 't');
+        assert_eq!(dirty(&mut siv), Some(true));
+
+        assert!(save(&mut siv, None));
+        assert_eq!(dirty(&mut siv), Some(false));
+        assert!(!Track::read(&first).unwrap().phrases[0][0].voiced);
+
+        // Undoing after a save is a change again, and "save as" moves where
+        // later saves go.
+        edit(&mut siv, 'u');
+        assert_eq!(dirty(&mut siv), Some(true));
+        assert!(save(&mut siv, Some(second.clone())));
+        assert_eq!(dirty(&mut siv), Some(false));
+        assert!(Track::read(&second).unwrap().phrases[0][0].voiced);
+        let path = siv.call_on_name("view", |v: &mut TrackView| v.path.clone());
+        assert_eq!(path, Some(second.clone()));
+
+        edit(&mut siv, 't');
+        assert!(save(&mut siv, None));
+        assert!(!Track::read(&second).unwrap().phrases[0][0].voiced);
+        assert!(!Track::read(&first).unwrap().phrases[0][0].voiced);
+
+        // A failed save leaves the changes unsaved.
+        edit(&mut siv, 't');
+        assert!(!save(
+            &mut siv,
+            Some(dir.join("missing").join("third.track"))
+        ));
+        assert_eq!(dirty(&mut siv), Some(true));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
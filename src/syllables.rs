@@ -0,0 +1,203 @@
+/// Hyphenation rules for splitting words into syllables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    English,
+    /// Splits between vowel groups without any one language's exceptions,
+    /// which suits many languages written in the Latin alphabet.
+    Other,
+}
+
+impl Language {
+    /// The language for a name or code such as `English`, `en` or `en-GB`.
+    pub fn from_name(name: &str) -> Language {
+        let name = name.trim().to_lowercase();
+        match name.split(['-', '_']).next() {
+            Some("en" | "eng" | "english") => Language::English,
+            _ => Language::Other,
+        }
+    }
+}
+
+/// Consonant groups that can start a syllable, besides single consonants.
+const ONSETS: [&str; 37] = [
+    "bl", "br", "ch", "cl", "cr", "dr", "dw", "fl", "fr", "gl", "gr", "kn", "ph", "pl", "pr", "qu",
+    "sc", "sh", "sk", "sl", "sm", "sn", "sp", "st", "sw", "th", "tr", "tw", "wh", "wr", "scr",
+    "shr", "spl", "spr", "squ", "str", "thr",
+];
+
+/// Pairs of letters sounded as one consonant. The first few start the
+/// next syllable and the rest end the one before.
+const DIGRAPHS: [&str; 8] = ["ch", "sh", "th", "ph", "wh", "ck", "ng", "gh"];
+const DIGRAPHS_STARTING: usize = 5;
+
+/// English vowel pairs sounded separately, as in "li-on", unless they come
+/// after a consonant they make a sound with, as in "na-tion".
+const HIATUSES: [&str; 3] = ["ia", "io", "ua"];
+
+/// Splits a word into syllables. A word with `-` in it is split there
+/// instead, so the split can be given by hand. Punctuation stays with the
+/// syllable next to it.
+pub fn split(word: &str, language: Language) -> Vec<String> {
+    if word.trim_matches('-').contains('-') {
+        return word
+            .split('-')
+            .filter(|syllable| !syllable.is_empty())
+            .map(str::to_string)
+            .collect();
+    }
+    let chars: Vec<char> = word.chars().collect();
+    let letters: Vec<char> = chars.iter().map(|c| c.to_ascii_lowercase()).collect();
+    let nuclei = nuclei(&letters, language);
+    let mut syllables = vec![];
+    let mut start = 0;
+    for pair in nuclei.windows(2) {
+        let at = split_point(&letters, pair[0].1, pair[1].0, language);
+        syllables.push(chars[start..at].iter().collect());
+        start = at;
+    }
+    syllables.push(chars[start..].iter().collect());
+    syllables
+}
+
+/// The vowel groups of a word that each make a syllable, as start and end
+/// indices.
+fn nuclei(letters: &[char], language: Language) -> Vec<(usize, usize)> {
+    let vowel = |i: usize| is_vowel(letters, i);
+    let mut nuclei: Vec<(usize, usize)> = vec![];
+    for i in 0..letters.len() {
+        if !vowel(i) {
+            continue;
+        }
+        match nuclei.last_mut() {
+            Some(last) if last.1 == i && !is_hiatus(letters, i, language) => last.1 = i + 1,
+            _ => nuclei.push((i, i + 1)),
+        }
+    }
+    if language == Language::English && nuclei.len() > 1 && is_silent_ending(letters) {
+        nuclei.pop();
+    }
+    nuclei
+}
+
+fn is_vowel(letters: &[char], i: usize) -> bool {
+    match letters[i] {
+        'a' | 'e' | 'i' | 'o' => true,
+        // The u of qu is part of the consonant.
+        'u' => i == 0 || letters[i - 1] != 'q',
+        // A y starting a word or a syllable, as in "yes" or "be-yond", is a
+        // consonant.
+        'y' => {
+            i > 0
+                && !letters
+                    .get(i + 1)
+                    .is_some_and(|next| "aeiou".contains(*next))
+        }
+        _ => false,
+    }
+}
+
+/// Whether the vowels at `i - 1` and `i` are sounded separately.
+fn is_hiatus(letters: &[char], i: usize, language: Language) -> bool {
+    if language != Language::English {
+        return false;
+    }
+    let pair: String = letters[i - 1..=i].iter().collect();
+    let after_sound = i >= 2 && "tscgx".contains(letters[i - 2]);
+    HIATUSES.contains(&pair.as_str()) && !after_sound
+}
+
+/// Whether the word ends in an `e` that isn't sounded, as in "make",
+/// "jumped" or "lives", but not "table", "wanted" or "boxes".
+fn is_silent_ending(letters: &[char]) -> bool {
+    let word: String = letters.iter().collect();
+    let word = word.trim_end_matches(|c: char| !c.is_alphabetic());
+    let before = |ending: &str| word[..word.len() - ending.len()].chars().last();
+    let consonant = |c: Option<char>| c.is_some_and(|c| c.is_alphabetic() && !"aeiouy".contains(c));
+    if word.ends_with("le") && consonant(before("le")) {
+        return false;
+    }
+    if word.ends_with("ee") {
+        return false;
+    }
+    if word.ends_with('e') {
+        return consonant(before("e"));
+    }
+    if word.ends_with("ed") {
+        return !matches!(before("ed"), Some('t' | 'd')) && consonant(before("ed"));
+    }
+    if let Some(stem) = word.strip_suffix("es") {
+        let sounded = ["s", "x", "z", "ch", "sh", "g", "c"];
+        return consonant(before("es")) && !sounded.iter().any(|end| stem.ends_with(end));
+    }
+    false
+}
+
+/// Where to split between the vowel group ending at `end` and the one
+/// starting at `start`: the next syllable gets the longest group of
+/// consonants that can start one, but at least one consonant stays behind
+/// when there are several.
+fn split_point(letters: &[char], end: usize, start: usize, language: Language) -> usize {
+    let cluster: String = letters[end..start].iter().collect();
+    if start - end < 2 {
+        return end;
+    }
+    if let Some(i) = DIGRAPHS.iter().position(|digraph| cluster == *digraph) {
+        return if i < DIGRAPHS_STARTING { end } else { start };
+    }
+    // A consonant before a final "le" goes with it, as in "ta-ble".
+    let ends_in_le =
+        language == Language::English && letters[start..] == ['e'] && cluster.ends_with('l');
+    if ends_in_le {
+        return start - 2;
+    }
+    (end + 1..start)
+        .find(|&i| {
+            let onset: String = letters[i..start].iter().collect();
+            ONSETS.contains(&onset.as_str()) || start - i == 1
+        })
+        .filter(|&i| letters[i..start].iter().all(|c| c.is_alphabetic()))
+        .unwrap_or(start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn english(word: &str) -> Vec<String> {
+        split(word, Language::English)
+    }
+
+    #[test]
+    fn splits_english_words() {
+        assert_eq!(english("beautiful"), ["beau", "ti", "ful"]);
+        assert_eq!(english("little"), ["lit", "tle"]);
+        assert_eq!(english("table"), ["ta", "ble"]);
+        assert_eq!(english("children"), ["chil", "dren"]);
+        assert_eq!(english("nation"), ["na", "tion"]);
+        assert_eq!(english("lion"), ["li", "on"]);
+        assert_eq!(english("pocket"), ["pock", "et"]);
+        assert_eq!(english("Hello,"), ["Hel", "lo,"]);
+    }
+
+    #[test]
+    fn keeps_silent_endings() {
+        assert_eq!(english("make"), ["make"]);
+        assert_eq!(english("jumped"), ["jumped"]);
+        assert_eq!(english("wanted"), ["wan", "ted"]);
+        assert_eq!(english("boxes"), ["bo", "xes"]);
+        assert_eq!(split("make", Language::Other), ["ma", "ke"]);
+    }
+
+    #[test]
+    fn splits_by_hand_at_hyphens() {
+        assert_eq!(english("o-ver-ride"), ["o", "ver", "ride"]);
+        assert_eq!(english("-"), ["-"]);
+    }
+
+    #[test]
+    fn names_languages() {
+        assert_eq!(Language::from_name("en-GB"), Language::English);
+        assert_eq!(Language::from_name(" English "), Language::English);
+        assert_eq!(Language::from_name("de"), Language::Other);
+    }
+}
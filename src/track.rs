@@ -1,177 +1,194 @@
 use std::cmp;
-use std::fs::File;
-use std::io::prelude::*;
+use std::fs;
+use std::ops::RangeInclusive;
 use std::path::Path;
 
-use crate::note::Note;
+use crate::note::{self, Note};
+use crate::syllables::{self, Language};
+use crate::track_file::{self, ParseError, ParseMode, TrackError, TrackHeader};
 
 pub type Phrase = Vec<Note>;
 pub type NoteIndex = (usize, usize);
 
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 pub struct Track {
     pub name: String,
+    pub header: TrackHeader,
     pub phrases: Vec<Phrase>,
     pub select_mode: SelectMode,
+    /// First selected note. In phrase mode only its phrase counts, and the
+    /// note is where the cursor goes back to in note mode.
     pub select_begin: NoteIndex,
+    /// Last selected note, which may be in a later phrase.
     pub select_end: NoteIndex,
-    seek_pos: u32,
-    seek_note_index: NoteIndex,
 }
 
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 pub enum SelectMode {
     Note,
     Phrase,
 }
 
+/// Notes or whole phrases copied out of a track.
+#[derive(Debug, Clone)]
+pub enum Clip {
+    /// Runs of notes from consecutive phrases. Pasting them breaks the
+    /// phrase they go into at the same places.
+    Notes(Vec<Phrase>),
+    Phrases(Vec<Phrase>),
+}
+
 impl Track {
     pub fn new() -> Self {
         Track {
             name: "NAME_PLACEHOLDER".to_string(),
+            header: TrackHeader::new(),
             phrases: Vec::new(),
             select_mode: SelectMode::Note,
             select_begin: (0, 0),
-            select_end: (1, 1),
-            seek_pos: 0,
-            seek_note_index: (0, 0),
+            select_end: (0, 0),
         }
     }
 
+    /// Inserts a note before the selection and selects it. In phrase mode
+    /// the note goes in a new phrase of its own.
     pub fn add_before(&mut self, note: Note) {
-        let (p, n) = self.select_begin;
-        match self.select_mode {
-            SelectMode::Note => {
-                if self.phrases.len() == 0 {
-                    self.phrases.push(Vec::new());
-                }
-                if self.phrases[p].len() == 0 {
-                    self.phrases[p].push(note);
-                    self.select_end.1 = n + 1
-                } else {
-                    self.phrases[p].insert(n, note);
-                }
-            }
-            SelectMode::Phrase => {
-                self.phrases[p].push(note);
-                self.select_begin.0 += 1;
-                self.select_end.0 += 1;
-            }
-        }
+        let clip = self.clip_of(note);
+        self.paste_before(&clip);
     }
 
+    /// Inserts a note after the selection and selects it. In phrase mode
+    /// the note goes in a new phrase of its own.
     pub fn add_after(&mut self, note: Note) {
-        let (p, n) = self.select_begin;
+        let clip = self.clip_of(note);
+        self.paste_after(&clip);
+    }
+
+    fn clip_of(&self, note: Note) -> Clip {
         match self.select_mode {
-            SelectMode::Note => {
-                if self.phrases.len() == 0 {
-                    self.phrases.push(Vec::new());
-                }
-                if self.phrases[p].len() == 0 || n == self.phrases[p].len() {
-                    self.phrases[p].push(note);
-                } else {
-                    self.phrases[p].insert(n + 1, note);
-                }
-                self.select_begin = (p, n + 1);
-                self.select_end = (p + 1, n + 2);
-            }
-            SelectMode::Phrase => {
-                if self.phrases.len() == 0 {
-                    self.phrases.push(Vec::new());
-                    self.phrases[0].push(note);
-                    self.select_begin = (0, 0);
-                    self.select_end = (1, 1);
-                } else {
-                    self.phrases.insert(p + 1, Vec::new());
-                    self.phrases[p + 1].push(note);
-                    self.select_begin = (p + 1, n);
-                    self.select_end = (p + 2, n + 1);
-                }
-            }
+            SelectMode::Note => Clip::Notes(vec![vec![note]]),
+            SelectMode::Phrase => Clip::Phrases(vec![vec![note]]),
         }
     }
 
+    /// The note after `(p, n)`, going on into later phrases.
+    fn next_note(&self, (p, n): NoteIndex) -> Option<NoteIndex> {
+        if n + 1 < self.phrases.get(p)?.len() {
+            return Some((p, n + 1));
+        }
+        (p + 1..self.phrases.len())
+            .find(|p| !self.phrases[*p].is_empty())
+            .map(|p| (p, 0))
+    }
+
+    /// The note before `(p, n)`, going back into earlier phrases.
+    fn prev_note(&self, (p, n): NoteIndex) -> Option<NoteIndex> {
+        if n > 0 {
+            return Some((p, n - 1));
+        }
+        (0..p)
+            .rev()
+            .find(|p| !self.phrases[*p].is_empty())
+            .map(|p| (p, self.phrases[p].len() - 1))
+    }
+
     pub fn select_prev(&mut self, delta: usize) {
-        let (_, n) = self.select_begin;
         match self.select_mode {
             SelectMode::Note => {
-                self.select_begin.1 = if self.select_begin.1 > delta {
-                    self.select_begin.1 - delta
-                } else {
-                    0
-                };
-                let (p, n) = self.select_begin;
-                self.select_end = (p + 1, n + 1);
+                let mut index = self.select_begin;
+                for _ in 0..delta {
+                    match self.prev_note(index) {
+                        Some(prev) => index = prev,
+                        None => break,
+                    }
+                }
+                self.select_note(index);
             }
             SelectMode::Phrase => {
-                let p = if self.select_begin.0 > delta {
-                    self.select_begin.0 - delta
-                } else {
-                    0
-                };
-                self.select_begin.0 = p;
-                self.select_begin.1 = cmp::min(n, self.phrases[p].len() - 1);
-                let (p, n) = self.select_begin;
-                self.select_end = if self.phrases[p].len() > 0 {
-                    (p + 1, n + 1)
-                } else {
-                    (p + 1, n)
-                }
+                self.select_phrase(self.select_begin.0.saturating_sub(delta));
             }
         }
     }
 
     pub fn select_next(&mut self, delta: usize) {
-        let (p, n) = self.select_begin;
         match self.select_mode {
             SelectMode::Note => {
-                self.select_begin.1 = cmp::min(n + delta, self.phrases[p].len() - 1);
-                let (p, n) = self.select_begin;
-                self.select_end = (p + 1, n + 1);
+                let mut index = self.select_end;
+                for _ in 0..delta {
+                    match self.next_note(index) {
+                        Some(next) => index = next,
+                        None => break,
+                    }
+                }
+                self.select_note(index);
             }
             SelectMode::Phrase => {
-                let p = cmp::min(p + delta, self.phrases.len() - 1);
-                self.select_begin.0 = p;
-                self.select_begin.1 = cmp::min(n, self.phrases[p].len() - 1);
-                let (p, n) = self.select_begin;
-                self.select_end = if self.phrases[p].len() > 0 {
-                    (p + 1, n + 1)
-                } else {
-                    (p + 1, n)
-                }
+                self.select_phrase(self.select_end.0 + delta);
             }
         }
     }
 
+    /// Adds the next note or phrase to the end of the selection. Notes are
+    /// added across phrase boundaries.
     pub fn extend_selection(&mut self) {
-        let (p, _) = self.select_begin;
         match self.select_mode {
             SelectMode::Note => {
-                if self.phrases[p].len() > 0 {
-                    self.select_end.1 = cmp::min(self.select_end.1 + 1, self.phrases[p].len());
+                if let Some(next) = self.next_note(self.select_end) {
+                    self.select_end = next;
                 }
             }
             SelectMode::Phrase => {
-                self.select_end.0 = cmp::min(self.select_end.0 + 1, self.phrases.len());
+                let last = self.phrases.len().saturating_sub(1);
+                self.select_end.0 = cmp::min(self.select_end.0 + 1, last);
             }
         }
     }
 
+    /// Drops the last note or phrase from the selection, down to one.
     pub fn contract_selection(&mut self) {
-        let (p, n) = self.select_begin;
         match self.select_mode {
             SelectMode::Note => {
-                self.select_end.1 = cmp::max(self.select_end.1 - 1, n + 1);
+                let begin = self.select_begin;
+                if let Some(prev) = self
+                    .prev_note(self.select_end)
+                    .filter(|prev| *prev >= begin)
+                {
+                    self.select_end = prev;
+                }
             }
             SelectMode::Phrase => {
-                self.select_end.0 = cmp::max(self.select_end.0 - 1, p + 1);
+                let end = self.select_end.0.saturating_sub(1);
+                self.select_end.0 = cmp::max(end, self.select_begin.0);
             }
         }
     }
 
     pub fn change_pitch(&mut self, pitch: i8) {
         self.apply_to_selection(&mut |note: &mut Note| {
-            note.pitch += pitch;
+            note.pitch = note
+                .pitch
+                .saturating_add(pitch)
+                .clamp(note::MIN_PITCH, note::MAX_PITCH);
+        });
+    }
+
+    /// Sets the pitch of the selected notes. Slides keep their end pitch.
+    pub fn set_pitch(&mut self, pitch: i8) {
+        self.apply_to_selection(&mut |note: &mut Note| {
+            note.slide_to = note.slide_to.filter(|end| *end != pitch);
+            note.pitch = pitch;
+        });
+    }
+
+    /// Moves the end pitch of the selected notes, turning them into slides.
+    /// A slide that ends where it starts is a plain note again.
+    pub fn change_slide(&mut self, pitch: i8) {
+        self.apply_to_selection(&mut |note: &mut Note| {
+            let end = note
+                .end_pitch()
+                .saturating_add(pitch)
+                .clamp(note::MIN_PITCH, note::MAX_PITCH);
+            note.slide_to = Some(end).filter(|end| *end != note.pitch);
         });
     }
 
@@ -189,126 +206,650 @@ impl Track {
         });
     }
 
+    pub fn cycle_kind(&mut self) {
+        self.apply_to_selection(&mut |note: &mut Note| {
+            note.kind = note.kind.next();
+        });
+    }
+
+    /// Joins the selected lyrics onto the word before them, or splits them
+    /// off into words of their own.
+    pub fn toggle_continues(&mut self) {
+        self.apply_to_selection(&mut |note: &mut Note| {
+            note.continues = !note.continues;
+        });
+    }
+
     pub fn change_lyrics(&mut self, lyric: &str) {
         self.apply_to_selection(&mut |note: &mut Note| {
             note.lyric = lyric.to_string();
         });
     }
 
+    /// Adds a phrase after the selection for each line of `lyrics`, with a
+    /// note for each syllable, and selects them.
+    pub fn paste_lyrics(&mut self, lyrics: &str, language: Language) {
+        let phrases: Vec<Phrase> = lyrics
+            .lines()
+            .map(|line| {
+                line.split_whitespace()
+                    .flat_map(|word| {
+                        syllables::split(word, language)
+                            .into_iter()
+                            .enumerate()
+                            .map(|(i, syllable)| {
+                                Note::new(note::DEFAULT_LENGTH, note::A4, true, syllable)
+                                    .with_continues(i > 0)
+                            })
+                    })
+                    .collect()
+            })
+            .filter(|phrase: &Phrase| !phrase.is_empty())
+            .collect();
+        self.paste_after(&Clip::Phrases(phrases));
+    }
+
     pub fn apply_to_selection(&mut self, f: &mut dyn FnMut(&mut Note)) {
-        match self.select_mode {
-            SelectMode::Phrase => {
-                for p in self.select_begin.0..self.select_end.0 {
-                    for note in &mut self.phrases[p] {
-                        f(note);
-                    }
-                }
-            }
-            SelectMode::Note => {
-                let mut p = self.select_begin.0;
-                let mut n = self.select_begin.1;
-                while p < self.select_end.0 - 1 {
-                    while n < self.phrases[p].len() {
-                        f(&mut self.phrases[p][n]);
-                    }
-                    n = 0;
-                    p += 1;
-                }
-                while n < self.select_end.1 {
-                    f(&mut self.phrases[p][n]);
-                    n += 1;
+        let selected = self.selected_notes();
+        for (p, phrase) in self.phrases.iter_mut().enumerate() {
+            for (n, note) in phrase.iter_mut().enumerate() {
+                if selected.contains(&(p, n)) {
+                    f(note);
                 }
             }
         }
     }
 
-    pub fn toggle_selection_mode(&mut self) {
+    /// The selected notes, or the selected phrases in phrase mode.
+    pub fn copy_selection(&self) -> Clip {
+        let (begin, end) = self.get_selection_bounds();
         match self.select_mode {
+            SelectMode::Note => {
+                let selected = self.selected_notes();
+                let runs = (begin.0..=end.0)
+                    .filter_map(|p| Some((p, self.phrases.get(p)?)))
+                    .map(|(p, phrase)| {
+                        (0..phrase.len())
+                            .filter(|n| selected.contains(&(p, *n)))
+                            .map(|n| phrase[n].clone())
+                            .collect()
+                    })
+                    .collect();
+                Clip::Notes(runs)
+            }
             SelectMode::Phrase => {
-                self.select_mode = SelectMode::Note;
-                let sb = self.select_begin;
-                let n = cmp::min(sb.1, self.phrases[sb.0].len() - 1);
-                self.select_begin = (sb.0, n);
-                self.select_end = (sb.0 + 1, n + 1);
+                let end = cmp::min(end.0 + 1, self.phrases.len());
+                Clip::Phrases(self.phrases[begin.0.min(end)..end].to_vec())
             }
+        }
+    }
+
+    /// Removes the selection and returns it. Cutting notes across a phrase
+    /// break joins what is left of the phrases on either side, and a phrase
+    /// left without notes is removed.
+    pub fn cut_selection(&mut self) -> Clip {
+        let clip = self.copy_selection();
+        if self.phrases.is_empty() {
+            return clip;
+        }
+        let (begin, end) = self.get_selection_bounds();
+        match self.select_mode {
             SelectMode::Note => {
-                self.select_mode = SelectMode::Phrase;
+                let last = &mut self.phrases[end.0];
+                let tail = last.split_off(cmp::min(end.1 + 1, last.len()));
+                self.phrases.drain(begin.0 + 1..=end.0);
+                let phrase = &mut self.phrases[begin.0];
+                phrase.truncate(begin.1);
+                phrase.extend(tail);
+                if phrase.is_empty() {
+                    self.phrases.remove(begin.0);
+                }
+                self.select_note(begin);
             }
-        };
+            SelectMode::Phrase => {
+                self.phrases.drain(begin.0..=end.0);
+                self.select_phrase(begin.0);
+            }
+        }
+        clip
     }
 
-    pub fn in_selection(&self, n: NoteIndex) -> bool {
-        let (pb, nb) = self.select_begin;
-        let (pe, ne) = self.select_end;
+    /// Inserts a copy of `clip` before the selection and selects it.
+    pub fn paste_before(&mut self, clip: &Clip) {
+        let (p, n) = self.select_begin;
         match self.select_mode {
-            SelectMode::Note => pb <= n.0 && n.0 < pe && nb <= n.1 && n.1 < ne,
-            SelectMode::Phrase => pb <= n.0 && n.0 < pe,
+            SelectMode::Note => self.paste(clip, (p, n)),
+            SelectMode::Phrase => self.paste(clip, (p, 0)),
         }
     }
 
-    pub fn get_selection_bounds(&self) -> (NoteIndex, NoteIndex) {
-        (self.select_begin, self.select_end)
+    /// Inserts a copy of `clip` after the selection and selects it.
+    pub fn paste_after(&mut self, clip: &Clip) {
+        let (p, n) = self.select_end;
+        let n = match self.select_mode {
+            SelectMode::Note => n + 1,
+            SelectMode::Phrase => usize::MAX,
+        };
+        match clip {
+            Clip::Notes(_) => self.paste(clip, (p, n)),
+            Clip::Phrases(_) => self.paste(clip, (p + 1, 0)),
+        }
     }
 
-    pub fn read(path: &Path) -> Result<Self, std::io::Error> {
-        let mut file = File::open(&path)?;
-        let mut s = String::new();
-        file.read_to_string(&mut s)?;
+    /// Inserts notes with lengths in milliseconds, such as sung ones, before
+    /// the selection and selects them. Their lengths are converted to the
+    /// track's resolution, and notes too short to have a length are dropped.
+    pub fn insert_timed(&mut self, notes: Vec<Note>) {
+        let (p, n) = match self.select_mode {
+            SelectMode::Note => self.select_begin,
+            SelectMode::Phrase => (self.select_begin.0, 0),
+        };
+        let position = match self.phrases.get(p) {
+            Some(phrase) => self.note_position((p, cmp::min(n, phrase.len()))),
+            None => 0,
+        };
+        let mut notes = self.lengths_from_ms(notes, position);
+        notes.retain(|note| note.length > 0);
+        self.paste_before(&Clip::Notes(vec![notes]));
+    }
 
-        let mut track = Self::new();
-        let phrases = s.split('\n').collect::<Vec<&str>>();
-        let mut phrases_iter = phrases.iter();
-        track.name = match phrases_iter.next() {
-            Some(s) => s.clone().to_string(),
-            None => return Err(std::io::Error::new(std::io::ErrorKind::Other, "Track file is empty")),
+    /// Times the voiced notes of phrase `p` to taps made along with the
+    /// audio. A tap is when a note starts and, if it was let go of before the
+    /// next one started, when it ends, in milliseconds from the start of the
+    /// audio. The gaps become rests, notes left untapped keep their lengths,
+    /// and later phrases move with the end of this one.
+    pub fn retime_phrase(&mut self, p: usize, taps: &[(u32, Option<u32>)]) {
+        let voiced: Vec<NoteIndex> = match self.phrases.get(p) {
+            Some(phrase) => (0..phrase.len())
+                .filter(|n| phrase[*n].voiced)
+                .map(|n| (p, n))
+                .collect(),
+            None => return,
+        };
+        let first = match taps.first() {
+            Some(tap) if !voiced.is_empty() => tap.0,
+            _ => return,
         };
-        for phrase in phrases_iter {
-            let notes = phrase.split('|');
-            let mut first = true;
-            for note in notes {
-                let fields: Vec<&str> = note.split(':').collect();
-                if fields.len() < 4 {
-                    continue;
+        let old_ms: Vec<u32> = voiced.iter().map(|i| self.note_length_ms(*i)).collect();
+        let syllables: Vec<Note> = voiced
+            .iter()
+            .map(|&(p, n)| self.phrases[p][n].clone())
+            .collect();
+
+        // A first note earlier than the phrase starts takes time from the gap
+        // or from the rest at the end of the phrase before.
+        let mut position = self.note_position((p, 0));
+        if p == 0 {
+            self.header.gap = first;
+        } else if let Some(rest) = self.phrases[p - 1].last_mut().filter(|note| !note.voiced) {
+            let since_gap = first.saturating_sub(self.header.gap);
+            let target = self.header.ms_to_position(since_gap as f64);
+            let earliest = position - rest.length;
+            if target < position {
+                let start = cmp::max(target, earliest);
+                rest.length -= position - start;
+                position = start;
+            }
+        }
+
+        let mut time = self.header.gap + self.header.position_to_ms(position).round() as u32;
+        let mut notes = vec![];
+        for (i, syllable) in syllables.into_iter().enumerate() {
+            let (onset, end) = match taps.get(i) {
+                Some(&(onset, release)) => {
+                    let next = taps.get(i + 1).map(|tap| tap.0);
+                    let end = match (release, next) {
+                        (Some(release), Some(next)) => release.min(next),
+                        (Some(release), None) => release,
+                        (None, Some(next)) => next,
+                        (None, None) => onset + old_ms[i],
+                    };
+                    (onset.max(time), end)
                 }
-                let voiced = if fields[0] == "u" { false } else { true };
-                let pitch = fields[1].parse::<i8>().unwrap();  //TODO handle bad files
-                let length = fields[2].parse::<u32>().unwrap();
-                let lyric = fields[3].to_string();
-                let note = Note::new(length, pitch, voiced, lyric);
-                if first {
-                    track.toggle_selection_mode();
-                    track.add_after(note);
-                    track.toggle_selection_mode();
-                    first = false;
-                } else {
-                    track.add_after(note);
+                None => (time, time + old_ms[i]),
+            };
+            if onset > time {
+                notes.push(Note::new(
+                    onset - time,
+                    syllable.pitch,
+                    false,
+                    String::new(),
+                ));
+            }
+            let length = end.saturating_sub(onset);
+            notes.push(Note { length, ..syllable });
+            time = cmp::max(end, onset);
+        }
+        let mut notes = self.lengths_from_ms(notes, position);
+        for note in notes.iter_mut().filter(|note| note.voiced) {
+            note.length = cmp::max(note.length, 1);
+        }
+        notes.retain(|note| note.length > 0);
+        self.phrases[p] = notes;
+        match self.select_mode {
+            SelectMode::Note => self.select_note((p, 0)),
+            SelectMode::Phrase => self.select_phrase(p),
+        }
+    }
+
+    /// Converts the lengths of `notes` from milliseconds to the track's
+    /// resolution, for notes that start at `position`. Notes too short for
+    /// the resolution end up with no length.
+    fn lengths_from_ms(&self, notes: Vec<Note>, position: u32) -> Vec<Note> {
+        let mut ms = self.header.position_to_ms(position);
+        let mut previous = position;
+        notes
+            .into_iter()
+            .map(|mut note| {
+                ms += note.length as f64;
+                let end = self.header.ms_to_position(ms);
+                note.length = end.saturating_sub(previous);
+                previous = end;
+                note
+            })
+            .collect()
+    }
+
+    /// Repeats the selection right after itself.
+    pub fn duplicate_selection(&mut self) {
+        let clip = self.copy_selection();
+        self.paste_after(&clip);
+    }
+
+    /// Inserts notes at `(p, n)`, or phrases at `p`, clamping to the track.
+    fn paste(&mut self, clip: &Clip, (p, n): NoteIndex) {
+        match clip {
+            Clip::Notes(runs) if runs.iter().any(|run| !run.is_empty()) => {
+                if self.phrases.is_empty() {
+                    self.phrases.push(Vec::new());
                 }
+                let p = cmp::min(p, self.phrases.len() - 1);
+                let n = cmp::min(n, self.phrases[p].len());
+                let tail = self.phrases[p].split_off(n);
+                self.phrases[p].extend(runs[0].iter().cloned());
+                self.phrases.splice(p + 1..p + 1, runs[1..].iter().cloned());
+                let last = p + runs.len() - 1;
+                let end = self.phrases[last].len();
+                self.phrases[last].extend(tail);
+                self.select_mode = SelectMode::Note;
+                self.select_begin = (p, n);
+                self.select_end = (last, end.saturating_sub(1));
+            }
+            Clip::Phrases(phrases) if !phrases.is_empty() => {
+                let p = cmp::min(p, self.phrases.len());
+                self.phrases.splice(p..p, phrases.iter().cloned());
+                self.select_mode = SelectMode::Phrase;
+                self.select_begin = (p, 0);
+                self.select_end = (p + phrases.len() - 1, 0);
             }
+            _ => {}
         }
-        Ok(track)
     }
 
-    pub fn write(&self, path: &Path) {
-        let display = path.display();
-        let mut file = match File::create(&path) {
-            Err(why) => panic!("Couldn't create {}: {}", display, why),
-            Ok(file) => file,
+    /// Selects the note at `(p, n)`, or the nearest one to it.
+    fn select_note(&mut self, (p, n): NoteIndex) {
+        self.select_mode = SelectMode::Note;
+        let p = cmp::min(p, self.phrases.len().saturating_sub(1));
+        let len = self.phrases.get(p).map_or(0, |phrase| phrase.len());
+        let n = cmp::min(n, len.saturating_sub(1));
+        self.select_begin = (p, n);
+        self.select_end = (p, n);
+    }
+
+    /// Selects phrase `p`, or the last phrase if there are fewer.
+    fn select_phrase(&mut self, p: usize) {
+        self.select_note((p, self.select_begin.1));
+        self.select_mode = SelectMode::Phrase;
+    }
+
+    /// Removes the selection, the same way cutting it does.
+    pub fn delete_selection(&mut self) {
+        self.cut_selection();
+    }
+
+    /// Splits the first selected note into one `length` long and one for
+    /// the rest, and selects the first part.
+    pub fn split_note(&mut self, length: u32) {
+        let (p, n) = self.select_begin;
+        let note = match self.phrases.get(p).and_then(|phrase| phrase.get(n)) {
+            Some(note) => note,
+            None => return,
         };
-        let mut s = String::new();
-        for phrase in &self.phrases {
-            for note in phrase {
-                let voiced = if note.voiced { "v" } else { "u" };
-                s += &format!("{}:{}:{}:{}|", voiced, note.pitch, note.length, note.lyric);
+        if let Some((first, second)) = note.split(length) {
+            self.phrases[p].splice(n..=n, [first, second]);
+            self.select_note((p, n));
+        }
+    }
+
+    /// Merges the selected notes in the first selected phrase into one, or
+    /// the selected note with the one after it. The merged note has the
+    /// first note's pitch and slides to the last one's end pitch.
+    pub fn merge_notes(&mut self) {
+        let (begin, end) = self.get_selection_bounds();
+        let (p, n) = begin;
+        let phrase = match self.phrases.get_mut(p) {
+            Some(phrase) => phrase,
+            None => return,
+        };
+        let last = if end.0 == p && end.1 > n {
+            end.1
+        } else {
+            n + 1
+        };
+        let last = cmp::min(last, phrase.len().saturating_sub(1));
+        if last <= n {
+            return;
+        }
+        let notes: Vec<Note> = phrase.drain(n..=last).collect();
+        let first = &notes[0];
+        let end_pitch = notes[notes.len() - 1].end_pitch();
+        let merged = Note {
+            length: notes.iter().map(|note| note.length).sum(),
+            lyric: note::phrase_lyrics(&notes),
+            ..first.clone()
+        }
+        .with_slide(Some(end_pitch).filter(|end| *end != first.pitch));
+        phrase.insert(n, merged);
+        self.select_note(begin);
+    }
+
+    /// Starts a new phrase at the first selected note.
+    pub fn break_phrase(&mut self) {
+        let (p, n) = self.select_begin;
+        match self.phrases.get(p) {
+            Some(phrase) if 0 < n && n < phrase.len() => {
+                let rest = self.phrases[p].split_off(n);
+                self.phrases.insert(p + 1, rest);
+                self.select_note((p + 1, 0));
             }
-            s += "\n";
+            _ => {}
+        }
+    }
+
+    /// Joins the selected phrases into one, or the selected phrase with the
+    /// one after it.
+    pub fn join_phrases(&mut self) {
+        let (begin, end) = self.get_selection_bounds();
+        let p = begin.0;
+        let last = cmp::max(end.0, p + 1);
+        let last = cmp::min(last, self.phrases.len().saturating_sub(1));
+        if last <= p {
+            return;
+        }
+        let joined: Vec<Note> = self.phrases.drain(p + 1..=last).flatten().collect();
+        self.phrases[p].extend(joined);
+        match self.select_mode {
+            SelectMode::Note => self.select_note(begin),
+            SelectMode::Phrase => self.select_phrase(p),
         }
-        match file.write_all(s.as_bytes()) {
-            Err(why) => panic!("Couldn't write {}: {}", display, why),
-            Ok(_) => println!("Wrote to {}", display),
+    }
+
+    pub fn toggle_selection_mode(&mut self) {
+        match self.select_mode {
+            SelectMode::Phrase => self.select_note(self.select_begin),
+            SelectMode::Note => self.select_mode = SelectMode::Phrase,
+        }
+    }
+
+    pub fn in_selection(&self, index: NoteIndex) -> bool {
+        self.selected_notes().contains(&index)
+    }
+
+    /// The first and last selected notes, both included. In phrase mode only
+    /// their phrases matter.
+    pub fn get_selection_bounds(&self) -> (NoteIndex, NoteIndex) {
+        (self.select_begin, self.select_end)
+    }
+
+    /// Every note index in the selection, which covers whole phrases in
+    /// phrase mode.
+    pub fn selected_notes(&self) -> RangeInclusive<NoteIndex> {
+        let (begin, end) = self.get_selection_bounds();
+        match self.select_mode {
+            SelectMode::Note => begin..=end,
+            SelectMode::Phrase => (begin.0, 0)..=(end.0, usize::MAX),
         }
     }
 
+    pub fn read(path: &Path) -> Result<Self, TrackError> {
+        let s = fs::read_to_string(path).map_err(|e| TrackError::io(path, e))?;
+        let (track, _) = track_file::parse(&s, path, ParseMode::Strict)?;
+        Ok(track)
+    }
+
+    /// Like `read`, but skips malformed notes and returns them as warnings.
+    pub fn read_lenient(path: &Path) -> Result<(Self, Vec<ParseError>), TrackError> {
+        let s = fs::read_to_string(path).map_err(|e| TrackError::io(path, e))?;
+        Ok(track_file::parse(&s, path, ParseMode::Lenient)?)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), TrackError> {
+        let s = track_file::serialize(self);
+        fs::write(path, s).map_err(|e| TrackError::io(path, e))
+    }
+
     pub fn get_phrase(&self, i: usize) -> Option<&Phrase> {
         self.phrases.get(i)
     }
+
+    /// Where a note starts, in lengths from the first note.
+    pub fn note_position(&self, (p, n): NoteIndex) -> u32 {
+        let before: u32 = self.phrases[..p]
+            .iter()
+            .flatten()
+            .map(|note| note.length)
+            .sum();
+        before
+            + self.phrases[p][..n]
+                .iter()
+                .map(|note| note.length)
+                .sum::<u32>()
+    }
+
+    /// Milliseconds from the start of the audio to the start of a note.
+    pub fn note_time(&self, index: NoteIndex) -> u32 {
+        let position = self.note_position(index);
+        self.header.gap + self.header.position_to_ms(position).round() as u32
+    }
+
+    pub fn note_length_ms(&self, index: NoteIndex) -> u32 {
+        let (p, n) = index;
+        let length = self.phrases[p][n].length;
+        self.header.length_to_ms(self.note_position(index), length)
+    }
+
+    /// The note playing `ms` milliseconds after the start of the audio.
+    pub fn note_at_time(&self, ms: u32) -> Option<NoteIndex> {
+        let since_gap = ms.checked_sub(self.header.gap)?;
+        let target = self.header.ms_to_position(since_gap as f64);
+        let mut position = 0;
+        for (p, phrase) in self.phrases.iter().enumerate() {
+            for (n, note) in phrase.iter().enumerate() {
+                if target < position + note.length {
+                    return Some((p, n));
+                }
+                position += note.length;
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(lengths: &[&[u32]]) -> Track {
+        let mut track = Track::new();
+        track.phrases = lengths
+            .iter()
+            .map(|phrase| {
+                phrase
+                    .iter()
+                    .map(|length| Note::new(*length, 60, true, String::new()))
+                    .collect()
+            })
+            .collect();
+        track
+    }
+
+    fn lengths(track: &Track) -> Vec<Vec<u32>> {
+        track
+            .phrases
+            .iter()
+            .map(|phrase| phrase.iter().map(|note| note.length).collect())
+            .collect()
+    }
+
+    #[test]
+    fn cut_and_paste_notes() {
+        let mut track = track(&[&[1, 2, 3], &[4]]);
+        track.select_begin = (0, 1);
+        track.select_end = (0, 2);
+        let clip = track.cut_selection();
+        assert_eq!(lengths(&track), [vec![1], vec![4]]);
+        track.paste_after(&clip);
+        assert_eq!(lengths(&track), [vec![1, 2, 3], vec![4]]);
+        assert_eq!(track.get_selection_bounds(), ((0, 1), (0, 2)));
+        track.duplicate_selection();
+        assert_eq!(lengths(&track), [vec![1, 2, 3, 2, 3], vec![4]]);
+    }
+
+    #[test]
+    fn cut_and_paste_phrases() {
+        let mut track = track(&[&[1], &[2], &[3]]);
+        track.toggle_selection_mode();
+        let clip = track.cut_selection();
+        assert_eq!(lengths(&track), [vec![2], vec![3]]);
+        track.select_next(1);
+        track.paste_after(&clip);
+        assert_eq!(lengths(&track), [vec![2], vec![3], vec![1]]);
+        let note = Note::new(5, 60, true, String::new());
+        track.paste_before(&Clip::Notes(vec![vec![note]]));
+        assert_eq!(lengths(&track), [vec![2], vec![3], vec![5, 1]]);
+    }
+
+    #[test]
+    fn selects_across_phrases() {
+        let mut track = track(&[&[1, 2], &[], &[3, 4]]);
+        track.select_next(1);
+        track.extend_selection();
+        track.extend_selection();
+        assert_eq!(track.get_selection_bounds(), ((0, 1), (2, 1)));
+        assert!(track.in_selection((2, 0)) && !track.in_selection((0, 0)));
+        track.contract_selection();
+        assert_eq!(track.get_selection_bounds(), ((0, 1), (2, 0)));
+        track.resize_note(10);
+        assert_eq!(lengths(&track), [vec![1, 12], vec![], vec![13, 4]]);
+
+        let clip = track.cut_selection();
+        assert_eq!(lengths(&track), [vec![1, 4]]);
+        track.paste_before(&clip);
+        assert_eq!(lengths(&track), [vec![1, 12], vec![], vec![13, 4]]);
+        assert_eq!(track.get_selection_bounds(), ((0, 1), (2, 0)));
+    }
+
+    #[test]
+    fn inserts_timed_notes_in_ticks() {
+        let mut track = track(&[&[4]]);
+        track.header.bpm = Some(120.0);
+        track.header.resolution = track_file::Resolution::Ticks { ticks_per_beat: 4 };
+        let notes = [250, 100, 10, 400]
+            .map(|ms| Note::new(ms, 60, true, String::new()))
+            .to_vec();
+        track.insert_timed(notes);
+        assert_eq!(lengths(&track), [vec![2, 1, 3, 4]]);
+        assert_eq!(track.get_selection_bounds(), ((0, 0), (0, 2)));
+    }
+
+    #[test]
+    fn retimes_phrase_to_taps() {
+        let mut track = track(&[&[100, 100], &[50, 100, 100, 100]]);
+        track.phrases[1][0].voiced = false;
+        track.retime_phrase(1, &[(300, None), (500, Some(600)), (700, None)]);
+        assert_eq!(
+            lengths(&track),
+            [vec![100, 100], vec![100, 200, 100, 100, 100]]
+        );
+        assert!(!track.phrases[1][0].voiced && !track.phrases[1][3].voiced);
+
+        track.retime_phrase(0, &[(1000, None)]);
+        assert_eq!(track.header.gap, 1000);
+        assert_eq!(lengths(&track)[0], [100, 100]);
+    }
+
+    #[test]
+    fn split_and_merge_notes() {
+        let mut track = track(&[&[4, 6]]);
+        track.phrases[0][1] = Note::new(6, 60, true, "hello".to_string()).with_slide(Some(66));
+        track.select_next(1);
+        track.split_note(2);
+        assert_eq!(lengths(&track), [vec![4, 2, 4]]);
+        let (first, second) = (&track.phrases[0][1], &track.phrases[0][2]);
+        assert_eq!((first.lyric.as_str(), second.lyric.as_str()), ("he", "llo"));
+        assert_eq!(
+            (first.slide_to, second.pitch, second.slide_to),
+            (Some(62), 62, Some(66))
+        );
+        assert!(second.continues);
+
+        track.extend_selection();
+        track.merge_notes();
+        assert_eq!(track.phrases[0][1].lyric, "hello");
+        assert_eq!(track.phrases[0][1].slide_to, Some(66));
+        assert_eq!(lengths(&track), [vec![4, 6]]);
+
+        track.break_phrase();
+        assert_eq!(lengths(&track), [vec![4], vec![6]]);
+        track.select_prev(1);
+        track.join_phrases();
+        assert_eq!(lengths(&track), [vec![4, 6]]);
+        track.delete_selection();
+        assert_eq!(lengths(&track), [vec![6]]);
+    }
+
+    #[test]
+    fn pastes_lyrics_as_syllables() {
+        let mut track = track(&[&[4]]);
+        track.paste_lyrics("Twinkle little\n\nstar-light", Language::English);
+        let lyrics: Vec<Vec<(&str, bool)>> = track
+            .phrases
+            .iter()
+            .map(|phrase| {
+                phrase
+                    .iter()
+                    .map(|note| (note.lyric.as_str(), note.continues))
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            lyrics,
+            [
+                vec![("", false)],
+                vec![
+                    ("Twin", false),
+                    ("kle", true),
+                    ("lit", false),
+                    ("tle", true)
+                ],
+                vec![("star", false), ("light", true)],
+            ]
+        );
+        assert_eq!((track.select_begin.0, track.select_end.0), (1, 2));
+    }
+
+    #[test]
+    fn sets_pitch_keeping_slides() {
+        let mut track = track(&[&[1, 2]]);
+        track.phrases[0][1].slide_to = Some(64);
+        track.select_end = (0, 1);
+        track.set_pitch(note::parse_pitch_name("Eb4").unwrap());
+        let pitches: Vec<(i8, Option<i8>)> = track.phrases[0]
+            .iter()
+            .map(|note| (note.pitch, note.slide_to))
+            .collect();
+        assert_eq!(pitches, [(63, None), (63, Some(64))]);
+        track.set_pitch(64);
+        assert_eq!(track.phrases[0][1].slide_to, None);
+    }
 }
@@ -0,0 +1,787 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::note::{Note, NoteKind};
+use crate::track::{Phrase, Track};
+
+/// The newest header version this build reads and the one it writes.
+pub const FORMAT_VERSION: u32 = 6;
+
+/// First version whose pitches are MIDI note numbers. Older files counted
+/// semitones from A and ignored the octave.
+const MIDI_PITCH_VERSION: u32 = 2;
+
+/// Unit of `Note::length`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resolution {
+    Milliseconds,
+    /// Lengths are ticks of a beat at the header's BPM.
+    Ticks {
+        ticks_per_beat: u32,
+    },
+}
+
+/// A new tempo from `position` on, in ticks from the first note.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TempoChange {
+    pub position: u32,
+    pub bpm: f32,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct TimeSignature {
+    pub beats_per_bar: u32,
+    /// Note value that gets one beat: 4 for quarter notes, 8 for eighths.
+    pub beat_unit: u32,
+}
+
+impl TimeSignature {
+    pub fn new() -> Self {
+        TimeSignature {
+            beats_per_bar: 4,
+            beat_unit: 4,
+        }
+    }
+}
+
+/// Timing information stored at the top of a `.track` file.
+///
+/// Legacy files have no header and get the default: version 0, lengths in
+/// milliseconds and no gap.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackHeader {
+    pub version: u32,
+    /// Tempo at the first note.
+    pub bpm: Option<f32>,
+    /// Later tempos, sorted by position. They only affect timing when lengths
+    /// are in ticks.
+    pub tempo_changes: Vec<TempoChange>,
+    pub time_signature: TimeSignature,
+    /// Time in milliseconds between the start of the audio and the first note.
+    pub gap: u32,
+    pub resolution: Resolution,
+}
+
+impl TrackHeader {
+    pub fn new() -> Self {
+        TrackHeader {
+            version: 0,
+            bpm: None,
+            tempo_changes: vec![],
+            time_signature: TimeSignature::new(),
+            gap: 0,
+            resolution: Resolution::Milliseconds,
+        }
+    }
+
+    /// Milliseconds from the first note to `position`.
+    pub fn position_to_ms(&self, position: u32) -> f64 {
+        let (ticks_per_beat, bpm) = match (self.resolution, self.bpm) {
+            (Resolution::Ticks { ticks_per_beat }, Some(bpm)) => (ticks_per_beat, bpm),
+            _ => return position as f64,
+        };
+        let tick_ms = |bpm: f32| 60000.0 / (bpm as f64 * ticks_per_beat as f64);
+        let (mut ms, mut at, mut bpm) = (0.0, 0, bpm);
+        for change in &self.tempo_changes {
+            if change.position >= position {
+                break;
+            }
+            ms += (change.position - at) as f64 * tick_ms(bpm);
+            at = change.position;
+            bpm = change.bpm;
+        }
+        ms + (position - at) as f64 * tick_ms(bpm)
+    }
+
+    /// The position `ms` milliseconds after the first note, rounded to the
+    /// nearest tick.
+    pub fn ms_to_position(&self, ms: f64) -> u32 {
+        let (ticks_per_beat, bpm) = match (self.resolution, self.bpm) {
+            (Resolution::Ticks { ticks_per_beat }, Some(bpm)) => (ticks_per_beat, bpm),
+            _ => return ms.max(0.0).round() as u32,
+        };
+        let tick_ms = |bpm: f32| 60000.0 / (bpm as f64 * ticks_per_beat as f64);
+        let (mut start_ms, mut at, mut bpm) = (0.0, 0, bpm);
+        for change in &self.tempo_changes {
+            let change_ms = start_ms + (change.position - at) as f64 * tick_ms(bpm);
+            if change_ms > ms {
+                break;
+            }
+            start_ms = change_ms;
+            at = change.position;
+            bpm = change.bpm;
+        }
+        at + ((ms - start_ms).max(0.0) / tick_ms(bpm)).round() as u32
+    }
+
+    /// Milliseconds taken by `length` starting at `position`. Rounding each
+    /// end keeps consecutive notes from drifting.
+    pub fn length_to_ms(&self, position: u32, length: u32) -> u32 {
+        let start = self.position_to_ms(position).round();
+        let end = self.position_to_ms(position.saturating_add(length)).round();
+        (end - start) as u32
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ParseMode {
+    /// Stop at the first malformed note.
+    Strict,
+    /// Skip malformed notes and report them as warnings.
+    Lenient,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Field {
+    Header,
+    Name,
+    Kind,
+    Pitch,
+    Length,
+    Lyric,
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Field::Header => "header",
+            Field::Name => "track name",
+            Field::Kind => "note kind",
+            Field::Pitch => "pitch",
+            Field::Length => "length",
+            Field::Lyric => "lyric",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A problem with a single note (or the header) of a `.track` file.
+///
+/// `line` is 1-based like a text editor. `note` is the 0-based position of the
+/// note within that line, and is 0 for header and name errors.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ParseError {
+    pub path: PathBuf,
+    pub line: usize,
+    pub note: usize,
+    pub field: Field,
+    pub value: String,
+    pub reason: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Field::Header | Field::Name = self.field {
+            return write!(
+                f,
+                "{}:{}: bad {} {:?}: {}",
+                self.path.display(),
+                self.line,
+                self.field,
+                self.value,
+                self.reason
+            );
+        }
+        write!(
+            f,
+            "{}:{}: note {}: bad {} {:?}: {}",
+            self.path.display(),
+            self.line,
+            self.note + 1,
+            self.field,
+            self.value,
+            self.reason
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug)]
+pub enum TrackError {
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    Parse(ParseError),
+}
+
+impl TrackError {
+    pub fn io(path: &Path, source: std::io::Error) -> Self {
+        TrackError::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for TrackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrackError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            TrackError::Parse(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for TrackError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TrackError::Io { source, .. } => Some(source),
+            TrackError::Parse(e) => Some(e),
+        }
+    }
+}
+
+impl From<ParseError> for TrackError {
+    fn from(e: ParseError) -> Self {
+        TrackError::Parse(e)
+    }
+}
+
+/// Parses the contents of a `.track` file.
+///
+/// A versioned file starts with `#version:N` followed by more `#key:value`
+/// header lines (`name`, `bpm`, `gap`, `resolution`, `ticks_per_beat`,
+/// `time_signature`, and a `tempo` line of `position:bpm` per change). A
+/// legacy file instead starts with the bare track name. Every following line
+/// is a phrase of `|`-separated notes, each written as
+/// `kind:pitch:length:lyric`. `kind` is `u` for unvoiced notes, and for
+/// voiced ones `v` (normal), `g` (golden), `f` (freestyle) or `r` (rap). A
+/// `+` after the kind means the lyric continues the previous lyric's word.
+/// `pitch` is a MIDI note number (before version 2, semitones from A), or
+/// `start>end` for a note that slides. The name and lyrics are escaped as
+/// described in `escape`, except for the bare name of a legacy file. In
+/// lenient mode, bad notes and header values are dropped and returned
+/// alongside the track instead of failing the whole file.
+pub fn parse(
+    s: &str,
+    path: &Path,
+    mode: ParseMode,
+) -> Result<(Track, Vec<ParseError>), ParseError> {
+    let mut lines = s.lines().enumerate().peekable();
+    let mut track = Track::new();
+    let mut warnings = vec![];
+    let error = |line: usize, note: usize, field: Field, value: &str, reason: &str| ParseError {
+        path: path.to_path_buf(),
+        line: line + 1,
+        note,
+        field,
+        value: value.to_string(),
+        reason: reason.to_string(),
+    };
+    let mut report = |e: ParseError| match mode {
+        ParseMode::Strict => Err(e),
+        ParseMode::Lenient => {
+            warnings.push(e);
+            Ok(())
+        }
+    };
+
+    match lines.peek() {
+        Some((_, line)) if line.starts_with("#version:") => {
+            let mut ticks_per_beat = None;
+            let mut ticks = false;
+            while let Some((i, line)) = lines.next_if(|(_, line)| line.starts_with('#')) {
+                let (key, value) = match split_unescaped(&line[1..], ':', 2)[..] {
+                    [key, value] => (key, value),
+                    _ => {
+                        report(error(i, 0, Field::Header, line, "expected #key:value"))?;
+                        continue;
+                    }
+                };
+                match key {
+                    "version" => match value.parse::<u32>() {
+                        Ok(v) if v <= FORMAT_VERSION => track.header.version = v,
+                        Ok(_) => {
+                            return Err(error(i, 0, Field::Header, value, "unsupported version"))
+                        }
+                        Err(e) => return Err(error(i, 0, Field::Header, value, &e.to_string())),
+                    },
+                    "name" => track.name = unescape(value),
+                    "bpm" => match value.parse::<f32>() {
+                        Ok(bpm) if bpm.is_finite() && bpm > 0.0 => track.header.bpm = Some(bpm),
+                        Ok(_) => report(error(i, 0, Field::Header, value, "bpm must be positive"))?,
+                        Err(e) => report(error(i, 0, Field::Header, value, &e.to_string()))?,
+                    },
+                    "gap" => match value.parse::<u32>() {
+                        Ok(gap) => track.header.gap = gap,
+                        Err(e) => report(error(i, 0, Field::Header, value, &e.to_string()))?,
+                    },
+                    "resolution" => match value {
+                        "ms" => ticks = false,
+                        "ticks" => ticks = true,
+                        _ => report(error(
+                            i,
+                            0,
+                            Field::Header,
+                            value,
+                            "expected 'ms' or 'ticks'",
+                        ))?,
+                    },
+                    "ticks_per_beat" => match value.parse::<u32>() {
+                        Ok(tpb) if tpb > 0 => ticks_per_beat = Some(tpb),
+                        Ok(_) => report(error(i, 0, Field::Header, value, "must be positive"))?,
+                        Err(e) => report(error(i, 0, Field::Header, value, &e.to_string()))?,
+                    },
+                    "tempo" => match parse_tempo_change(value) {
+                        Ok(change) => track.header.tempo_changes.push(change),
+                        Err(reason) => report(error(i, 0, Field::Header, value, &reason))?,
+                    },
+                    "time_signature" => match parse_time_signature(value) {
+                        Some(signature) => track.header.time_signature = signature,
+                        None => report(error(
+                            i,
+                            0,
+                            Field::Header,
+                            value,
+                            "expected beats/unit such as 3/4",
+                        ))?,
+                    },
+                    _ => report(error(i, 0, Field::Header, key, "unknown header key"))?,
+                }
+            }
+            // Later lines win when two changes share a position.
+            track.header.tempo_changes.reverse();
+            track
+                .header
+                .tempo_changes
+                .sort_by_key(|change| change.position);
+            track
+                .header
+                .tempo_changes
+                .dedup_by_key(|change| change.position);
+            if ticks {
+                match (ticks_per_beat, track.header.bpm) {
+                    (Some(ticks_per_beat), Some(_)) => {
+                        track.header.resolution = Resolution::Ticks { ticks_per_beat }
+                    }
+                    _ => report(error(
+                        0,
+                        0,
+                        Field::Header,
+                        "ticks",
+                        "tick resolution needs bpm and ticks_per_beat",
+                    ))?,
+                }
+            }
+        }
+        Some((_, name)) => {
+            // Legacy names were written as is, so backslashes are literal.
+            track.name = name.to_string();
+            lines.next();
+        }
+        None => return Err(error(0, 0, Field::Name, "", "track file is empty")),
+    }
+
+    for (i, line) in lines {
+        let mut phrase: Phrase = vec![];
+        for (n, note) in split_unescaped(line, '|', usize::MAX)
+            .into_iter()
+            .enumerate()
+        {
+            if note.is_empty() {
+                continue;
+            }
+            match parse_note(note) {
+                Ok(note) => phrase.push(note),
+                Err((field, value, reason)) => report(error(i, n, field, &value, &reason))?,
+            }
+        }
+        if !phrase.is_empty() {
+            track.phrases.push(phrase);
+        }
+    }
+    if track.header.version < MIDI_PITCH_VERSION {
+        // Old pitches carry no octave, so they're placed in the fourth.
+        for note in track.phrases.iter_mut().flatten() {
+            note.pitch = 60 + (note.pitch as i32 + 9).rem_euclid(12) as i8;
+        }
+    }
+    Ok((track, warnings))
+}
+
+/// Parses `position:bpm`.
+fn parse_tempo_change(s: &str) -> Result<TempoChange, String> {
+    let (position, bpm) = s
+        .split_once(':')
+        .ok_or_else(|| "expected position:bpm".to_string())?;
+    let position = position.parse::<u32>().map_err(|e| e.to_string())?;
+    match bpm.parse::<f32>() {
+        Ok(bpm) if bpm.is_finite() && bpm > 0.0 => Ok(TempoChange { position, bpm }),
+        Ok(_) => Err("bpm must be positive".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn parse_time_signature(s: &str) -> Option<TimeSignature> {
+    let (beats_per_bar, beat_unit) = s.split_once('/')?;
+    let signature = TimeSignature {
+        beats_per_bar: beats_per_bar.parse().ok()?,
+        beat_unit: beat_unit.parse().ok()?,
+    };
+    if signature.beats_per_bar > 0 && signature.beat_unit.is_power_of_two() {
+        Some(signature)
+    } else {
+        None
+    }
+}
+
+fn parse_note(s: &str) -> Result<Note, (Field, String, String)> {
+    let mut fields = split_unescaped(s, ':', 4).into_iter();
+    let mut next = |field: Field| {
+        fields
+            .next()
+            .ok_or_else(|| (field, s.to_string(), "missing field".to_string()))
+    };
+
+    let kind = next(Field::Kind)?;
+    let (kind, continues) = match kind.strip_suffix('+') {
+        Some(kind) => (kind, true),
+        None => (kind, false),
+    };
+    let pitch = next(Field::Pitch)?;
+    let length = next(Field::Length)?;
+    let lyric = next(Field::Lyric)?;
+
+    let (voiced, kind) = match kind {
+        "v" => (true, NoteKind::Normal),
+        "g" => (true, NoteKind::Golden),
+        "f" => (true, NoteKind::Freestyle),
+        "r" => (true, NoteKind::Rap),
+        "u" => (false, NoteKind::Normal),
+        _ => {
+            return Err((
+                Field::Kind,
+                kind.to_string(),
+                "expected 'v', 'g', 'f', 'r' or 'u'".to_string(),
+            ))
+        }
+    };
+    let parse_pitch = |p: &str| {
+        p.parse::<i8>()
+            .map_err(|e| (Field::Pitch, pitch.to_string(), e.to_string()))
+    };
+    let (pitch, slide_to) = match pitch.split_once('>') {
+        Some((start, end)) => (parse_pitch(start)?, Some(parse_pitch(end)?)),
+        None => (parse_pitch(pitch)?, None),
+    };
+    let length = length
+        .parse::<u32>()
+        .map_err(|e| (Field::Length, length.to_string(), e.to_string()))?;
+
+    Ok(Note::new(length, pitch, voiced, unescape(lyric))
+        .with_kind(kind)
+        .with_continues(continues)
+        .with_slide(slide_to))
+}
+
+/// Serializes a track into the format read by `parse`.
+///
+/// Phrases without notes have no representation and are left out.
+pub fn serialize(track: &Track) -> String {
+    let header = &track.header;
+    let mut s = format!("#version:{}\n", FORMAT_VERSION);
+    s += &format!("#name:{}\n", escape(&track.name));
+    if let Some(bpm) = header.bpm {
+        s += &format!("#bpm:{}\n", bpm);
+    }
+    s += &format!("#gap:{}\n", header.gap);
+    match header.resolution {
+        Resolution::Milliseconds => s += "#resolution:ms\n",
+        Resolution::Ticks { ticks_per_beat } => {
+            s += "#resolution:ticks\n";
+            s += &format!("#ticks_per_beat:{}\n", ticks_per_beat);
+        }
+    }
+    for change in &header.tempo_changes {
+        s += &format!("#tempo:{}:{}\n", change.position, change.bpm);
+    }
+    let signature = header.time_signature;
+    s += &format!(
+        "#time_signature:{}/{}\n",
+        signature.beats_per_bar, signature.beat_unit
+    );
+    for phrase in track.phrases.iter().filter(|phrase| !phrase.is_empty()) {
+        let notes: Vec<String> = phrase
+            .iter()
+            .map(|note| {
+                let kind = match (note.voiced, note.kind) {
+                    (false, _) => "u",
+                    (true, NoteKind::Normal) => "v",
+                    (true, NoteKind::Golden) => "g",
+                    (true, NoteKind::Freestyle) => "f",
+                    (true, NoteKind::Rap) => "r",
+                };
+                let continues = if note.continues { "+" } else { "" };
+                let pitch = match note.slide_to {
+                    Some(end) => format!("{}>{}", note.pitch, end),
+                    None => note.pitch.to_string(),
+                };
+                format!(
+                    "{}{}:{}:{}:{}",
+                    kind,
+                    continues,
+                    pitch,
+                    note.length,
+                    escape(&note.lyric)
+                )
+            })
+            .collect();
+        s += &notes.join("|");
+        s += "\n";
+    }
+    s
+}
+
+/// Escapes text so it can be stored in a name or lyric field.
+///
+/// Backslash, `|` and `:` are prefixed with a backslash, and line breaks are
+/// written as `\n` and `\r`.
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' | '|' | ':' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Reverses `escape`. A trailing lone backslash is kept as is.
+pub fn unescape(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// Splits `s` at unescaped occurrences of `sep` into at most `limit` pieces.
+/// The pieces are returned still escaped.
+fn split_unescaped(s: &str, sep: char, limit: usize) -> Vec<&str> {
+    let mut pieces = vec![];
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == sep && pieces.len() + 1 < limit {
+            pieces.push(&s[start..i]);
+            start = i + c.len_utf8();
+        }
+    }
+    pieces.push(&s[start..]);
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn arb_note() -> impl Strategy<Value = Note> {
+        (
+            any::<u32>(),
+            any::<i8>(),
+            prop::option::of(prop::sample::select(&NoteKind::ALL[..])),
+            any::<String>(),
+            any::<bool>(),
+            any::<Option<i8>>(),
+        )
+            .prop_map(|(length, pitch, kind, lyric, continues, slide_to)| {
+                let note = match kind {
+                    Some(kind) => Note::new(length, pitch, true, lyric).with_kind(kind),
+                    None => Note::new(length, pitch, false, lyric),
+                };
+                note.with_continues(continues).with_slide(slide_to)
+            })
+    }
+
+    fn arb_header() -> impl Strategy<Value = TrackHeader> {
+        (
+            prop::option::of(1.0f32..400.0),
+            any::<u32>(),
+            prop::option::of(1u32..1000),
+            prop::collection::btree_map(any::<u32>(), 1.0f32..400.0, 0..4),
+            (1u32..16, prop::sample::select(vec![1u32, 2, 4, 8, 16])),
+        )
+            .prop_map(
+                |(bpm, gap, ticks_per_beat, tempos, (beats_per_bar, beat_unit))| {
+                    let resolution = match (bpm, ticks_per_beat) {
+                        (Some(_), Some(ticks_per_beat)) => Resolution::Ticks { ticks_per_beat },
+                        _ => Resolution::Milliseconds,
+                    };
+                    TrackHeader {
+                        version: FORMAT_VERSION,
+                        bpm,
+                        tempo_changes: tempos
+                            .into_iter()
+                            .map(|(position, bpm)| TempoChange { position, bpm })
+                            .collect(),
+                        time_signature: TimeSignature {
+                            beats_per_bar,
+                            beat_unit,
+                        },
+                        gap,
+                        resolution,
+                    }
+                },
+            )
+    }
+
+    fn arb_track() -> impl Strategy<Value = Track> {
+        (
+            any::<String>(),
+            arb_header(),
+            prop::collection::vec(prop::collection::vec(arb_note(), 1..8), 0..8),
+        )
+            .prop_map(|(name, header, phrases)| {
+                let mut track = Track::new();
+                track.name = name;
+                track.header = header;
+                track.phrases = phrases;
+                track
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn escape_round_trip(s in any::<String>()) {
+            prop_assert_eq!(unescape(&escape(&s)), s);
+        }
+
+        #[test]
+        fn write_read_round_trip(track in arb_track()) {
+            let s = serialize(&track);
+            let (read, warnings) = parse(&s, Path::new("test.track"), ParseMode::Strict).unwrap();
+            prop_assert!(warnings.is_empty());
+            prop_assert_eq!(read.name, track.name);
+            prop_assert_eq!(read.header, track.header);
+            prop_assert_eq!(read.phrases, track.phrases);
+        }
+    }
+
+    #[test]
+    fn reads_legacy_trailing_separator() {
+        let s = "lead\nv:70:8:la|u:0:4:|\nv:72:8:di|\n";
+        let (track, _) = parse(s, Path::new("test.track"), ParseMode::Strict).unwrap();
+        assert_eq!(track.phrases.len(), 2);
+        assert_eq!(track.phrases[0].len(), 2);
+        assert_eq!(track.phrases[1][0].lyric, "di");
+        assert_eq!(track.header, TrackHeader::new());
+    }
+
+    #[test]
+    fn keeps_legacy_name_unescaped() {
+        let s = "C:\\songs\\lead\nv:70:8:la\n";
+        let (track, _) = parse(s, Path::new("test.track"), ParseMode::Strict).unwrap();
+        assert_eq!(track.name, "C:\\songs\\lead");
+    }
+
+    #[test]
+    fn reports_where_errors_are() {
+        let path = Path::new("test.track");
+        let error = |s: &str| parse(s, path, ParseMode::Strict).unwrap_err();
+
+        let e = error("#version:6\n#name:lead\n#bpm:fast\n");
+        assert_eq!(
+            (e.line, e.field, e.value.as_str()),
+            (3, Field::Header, "fast")
+        );
+        let e = error("#version:6\n#gap\n");
+        assert_eq!((e.line, e.field), (2, Field::Header));
+        let e = error("#version:9\n");
+        assert_eq!((e.line, e.reason.as_str()), (1, "unsupported version"));
+
+        let e = error("lead\nv:70:8:la\nv:70:8:la|v:high:8:di\n");
+        assert_eq!((e.line, e.note, e.field), (3, 1, Field::Pitch));
+        assert_eq!(e.value, "high");
+        let e = error("lead\nv:70:-8:la\n");
+        assert_eq!((e.line, e.note, e.field), (2, 0, Field::Length));
+        let e = error("lead\nx:70:8:la\n");
+        assert_eq!((e.line, e.field, e.value.as_str()), (2, Field::Kind, "x"));
+        // An escaped separator doesn't split fields, so the note runs short.
+        let e = error("lead\nv\\:70:8:la\n");
+        assert_eq!((e.line, e.field), (2, Field::Lyric));
+        assert_eq!(e.reason, "missing field");
+        assert_eq!(
+            e.to_string(),
+            "test.track:2: note 1: bad lyric \"v\\\\:70:8:la\": missing field"
+        );
+    }
+
+    #[test]
+    fn lenient_mode_skips_bad_notes() {
+        let s = "#version:6\n#name:lead\n#bpm:0\nv:70:8:la|v:70:x:bad|u:0:4:\nv:72:8:di|q:0:0:\n";
+        let path = Path::new("test.track");
+        assert!(parse(s, path, ParseMode::Strict).is_err());
+        let (track, warnings) = parse(s, path, ParseMode::Lenient).unwrap();
+        let places: Vec<_> = warnings.iter().map(|e| (e.line, e.note, e.field)).collect();
+        assert_eq!(
+            places,
+            [
+                (3, 0, Field::Header),
+                (4, 1, Field::Length),
+                (5, 1, Field::Kind)
+            ]
+        );
+        assert_eq!(track.header.bpm, None);
+        let lyrics: Vec<Vec<&str>> = track
+            .phrases
+            .iter()
+            .map(|phrase| phrase.iter().map(|note| note.lyric.as_str()).collect())
+            .collect();
+        assert_eq!(lyrics, [vec!["la", ""], vec!["di"]]);
+    }
+
+    #[test]
+    fn reads_legacy_pitch_into_fourth_octave() {
+        let s = "lead\nv:0:8:la|v:3:8:la|v:-1:8:la|v:14:8:la\n";
+        let (track, _) = parse(s, Path::new("test.track"), ParseMode::Strict).unwrap();
+        let pitches: Vec<i8> = track.phrases[0].iter().map(|note| note.pitch).collect();
+        assert_eq!(pitches, [69, 60, 68, 71]);
+    }
+
+    #[test]
+    fn converts_across_tempo_changes() {
+        let s =
+            "#version:4\n#name:lead\n#bpm:120\n#resolution:ticks\n#ticks_per_beat:4\n#tempo:8:60\n";
+        let (track, _) = parse(s, Path::new("test.track"), ParseMode::Strict).unwrap();
+        let header = &track.header;
+        assert_eq!(header.position_to_ms(8), 1000.0);
+        assert_eq!(header.position_to_ms(12), 2000.0);
+        assert_eq!(header.length_to_ms(6, 4), 750);
+        assert_eq!(header.ms_to_position(1500.0), 10);
+    }
+
+    #[test]
+    fn reads_tick_header() {
+        let s = "#version:3\n#name:lead\n#bpm:120\n#gap:250\n#resolution:ticks\n#ticks_per_beat:4\nv:70:2:la\n";
+        let (track, _) = parse(s, Path::new("test.track"), ParseMode::Strict).unwrap();
+        assert_eq!(track.name, "lead");
+        assert_eq!(track.header.gap, 250);
+        assert_eq!(
+            track.header.length_to_ms(0, track.phrases[0][0].length),
+            250
+        );
+    }
+}
@@ -0,0 +1,553 @@
+use std::fs;
+use std::path::Path;
+
+use crate::format_error::FormatError;
+use crate::note::{self, Note, NoteKind};
+use crate::song::{self, Song};
+use crate::track::{Phrase, Track};
+use crate::track_file::{Resolution, TrackHeader, FORMAT_VERSION};
+
+/// UltraStar counts notes in quarters of the `#BPM` header's beat.
+pub const TICKS_PER_BEAT: u32 = 4;
+
+/// UltraStar pitch 0 is middle C.
+pub const PITCH_OFFSET: i32 = 60;
+
+/// Notes and phrases collected for one singer while reading a file.
+struct Singer {
+    phrases: Vec<Phrase>,
+    phrase: Phrase,
+    first_beat: Option<i64>,
+    cursor: i64,
+    offset: i64,
+}
+
+impl Singer {
+    fn new() -> Self {
+        Singer {
+            phrases: vec![],
+            phrase: vec![],
+            first_beat: None,
+            cursor: 0,
+            offset: 0,
+        }
+    }
+
+    /// Fills the time up to `beat` with a rest.
+    fn rest_until(&mut self, beat: i64, pitch: i8) {
+        if self.first_beat.is_some() && beat > self.cursor {
+            let length = (beat - self.cursor) as u32;
+            self.phrase
+                .push(Note::new(length, pitch, false, "".to_string()));
+            self.cursor = beat;
+        }
+    }
+
+    fn add_note(&mut self, start: i64, length: i64, note: Note) {
+        let start = self.offset + start;
+        self.rest_until(start, note.pitch);
+        if self.first_beat.is_none() {
+            self.first_beat = Some(start);
+        }
+        self.cursor = self.cursor.max(start) + length;
+        self.phrase.push(note);
+    }
+
+    /// Ends the current phrase at `beat`. In relative mode the following
+    /// notes count from `next_start`, or from `beat` if it is missing.
+    fn break_line(&mut self, beat: i64, next_start: Option<i64>, relative: bool) {
+        if let Some(last) = self.phrase.last() {
+            let pitch = last.pitch;
+            self.rest_until(self.offset + beat, pitch);
+            self.phrases.push(std::mem::take(&mut self.phrase));
+        }
+        if relative {
+            self.offset += next_start.unwrap_or(beat);
+        }
+    }
+
+    fn finish(&mut self) {
+        if !self.phrase.is_empty() {
+            self.phrases.push(std::mem::take(&mut self.phrase));
+        }
+    }
+}
+
+/// Whether `s` has the `#TITLE` and `#BPM` headers of an UltraStar chart,
+/// which tells charts apart from other text files.
+pub fn is_chart(s: &str) -> bool {
+    let has_header = |name: &str| {
+        s.lines().any(|line| {
+            let line = line.trim_start_matches('\u{feff}');
+            line.strip_prefix('#')
+                .and_then(|header| header.split_once(':'))
+                .is_some_and(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+        })
+    };
+    has_header("TITLE") && has_header("BPM")
+}
+
+/// Parses an UltraStar `.txt` chart into a `Song`.
+///
+/// Cover, video and audio file names are resolved relative to `path`.
+pub fn parse(s: &str, path: &Path) -> Result<Song, FormatError> {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut title = None;
+    let mut artist = String::new();
+    let mut cover = None;
+    let mut video = None;
+    let mut audio = None;
+    let mut bpm = None;
+    let mut gap = 0.0;
+    let mut relative = false;
+    let mut duet_names = [None, None];
+
+    let mut singers = vec![Singer::new()];
+    let mut current = 0;
+
+    for (i, line) in s.lines().enumerate() {
+        let line_number = i + 1;
+        let line = line.trim_start_matches('\u{feff}').trim_end_matches('\r');
+        let error = |reason: &str| FormatError::parse(path, line_number, reason);
+
+        if let Some(header) = line.strip_prefix('#') {
+            let (key, value) = match header.split_once(':') {
+                Some((key, value)) => (key.trim().to_uppercase(), value.trim()),
+                None => continue,
+            };
+            match key.as_str() {
+                "TITLE" => title = Some(value.to_string()),
+                "ARTIST" => artist = value.to_string(),
+                "MP3" | "AUDIO" => audio = Some(value.to_string()),
+                "COVER" => cover = Some(value.to_string()),
+                "VIDEO" => video = Some(value.to_string()),
+                "BPM" => bpm = Some(parse_decimal(value).ok_or_else(|| error("bad BPM"))?),
+                "GAP" => gap = parse_decimal(value).ok_or_else(|| error("bad GAP"))?,
+                "RELATIVE" => relative = value.eq_ignore_ascii_case("yes"),
+                "DUETSINGERP1" | "P1" => duet_names[0] = Some(value.to_string()),
+                "DUETSINGERP2" | "P2" => duet_names[1] = Some(value.to_string()),
+                _ => (),
+            }
+            continue;
+        }
+
+        let kind = match line.chars().next() {
+            Some(c) => c,
+            None => continue,
+        };
+        match kind {
+            ':' | '*' | 'F' | 'R' | 'G' => {
+                let (fields, text) = split_fields(&line[1..], 3)
+                    .ok_or_else(|| error("expected start, length, pitch and text"))?;
+                let start = fields[0]
+                    .parse::<i64>()
+                    .map_err(|_| error("bad note start"))?;
+                let length = fields[1]
+                    .parse::<i64>()
+                    .map_err(|_| error("bad note length"))?;
+                let pitch = fields[2]
+                    .parse::<i32>()
+                    .map_err(|_| error("bad note pitch"))?;
+                if length < 0 {
+                    return Err(error("negative note length"));
+                }
+                let pitch = (pitch + PITCH_OFFSET).clamp(i8::MIN as i32, i8::MAX as i32) as i8;
+                // Golden rap notes have no kind of their own and stay rap.
+                let kind = match kind {
+                    '*' => NoteKind::Golden,
+                    'F' => NoteKind::Freestyle,
+                    'R' | 'G' => NoteKind::Rap,
+                    _ => NoteKind::Normal,
+                };
+                let note = Note::new(length as u32, pitch, true, text.to_string()).with_kind(kind);
+                singers[current].add_note(start, length, note);
+            }
+            '-' => {
+                let mut beats = line[1..].split_whitespace().map(|beat| beat.parse::<i64>());
+                let beat = match beats.next() {
+                    Some(Ok(beat)) => beat,
+                    _ => return Err(error("bad line break")),
+                };
+                let next_start = match beats.next() {
+                    Some(Ok(start)) => Some(start),
+                    Some(Err(_)) => return Err(error("bad line break")),
+                    None => None,
+                };
+                singers[current].break_line(beat, next_start, relative);
+            }
+            'P' => {
+                current = match line[1..].trim() {
+                    "1" => 0,
+                    "2" => 1,
+                    _ => return Err(error("unknown singer")),
+                };
+                if singers.len() <= current {
+                    singers.push(Singer::new());
+                }
+            }
+            'E' => break,
+            _ => return Err(error("unrecognized line")),
+        }
+    }
+
+    let bpm = bpm.ok_or_else(|| FormatError::parse(path, 0, "missing #BPM"))?;
+    if bpm <= 0.0 {
+        return Err(FormatError::parse(path, 0, "#BPM must be positive"));
+    }
+    let title = title.unwrap_or_else(|| {
+        path.file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default()
+    });
+
+    let mut tracks = vec![];
+    let duet = singers.len() > 1;
+    for (i, mut singer) in singers.into_iter().enumerate() {
+        singer.finish();
+        let first_beat = match singer.first_beat {
+            Some(beat) => beat,
+            None => continue,
+        };
+        let beat_ms = 60000.0 / (bpm * TICKS_PER_BEAT as f64);
+        let mut track = Track::new();
+        track.name = match (duet, &duet_names[i]) {
+            (false, _) => title.clone(),
+            (true, Some(name)) => name.clone(),
+            (true, None) => format!("P{}", i + 1),
+        };
+        track.header = TrackHeader {
+            version: FORMAT_VERSION,
+            bpm: Some(bpm as f32),
+            gap: (gap + first_beat as f64 * beat_ms).max(0.0).round() as u32,
+            resolution: Resolution::Ticks {
+                ticks_per_beat: TICKS_PER_BEAT,
+            },
+            ..TrackHeader::new()
+        };
+        track.phrases = singer.phrases;
+        for phrase in &mut track.phrases {
+            note::mark_word_boundaries(phrase);
+            for note in phrase.iter_mut() {
+                // A `~` holds the previous syllable and has no text of its own.
+                if note.lyric == "~" {
+                    note.lyric.clear();
+                }
+            }
+        }
+        tracks.push(track);
+    }
+    if tracks.is_empty() {
+        return Err(FormatError::parse(path, 0, "chart has no notes"));
+    }
+
+    let album_cover = cover.map(|cover| {
+        let mut image = song::Image::new();
+        image.load_image(&dir.join(cover));
+        image
+    });
+    let video_path = video.map(|video| dir.join(video)).filter(|p| p.exists());
+    let mut song = Song::new(title, artist, String::new(), album_cover, video_path);
+    song.audio_path = audio.map(|audio| dir.join(audio));
+    for track in tracks {
+        song.add_track(track.name.clone(), track);
+    }
+    Ok(song)
+}
+
+/// Parses a number that may use a comma as its decimal separator.
+fn parse_decimal(s: &str) -> Option<f64> {
+    s.replace(',', ".")
+        .parse::<f64>()
+        .ok()
+        .filter(|n| n.is_finite())
+}
+
+/// Splits `count` whitespace-separated fields off the front of `s`. The text
+/// after them starts after a single separator so that a leading space, which
+/// UltraStar uses to start a new word, is kept.
+fn split_fields(s: &str, count: usize) -> Option<(Vec<&str>, &str)> {
+    let mut rest = s;
+    let mut fields = vec![];
+    for _ in 0..count {
+        rest = rest.trim_start();
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        if end == 0 {
+            return None;
+        }
+        fields.push(&rest[..end]);
+        rest = &rest[end..];
+    }
+    let text = rest.strip_prefix([' ', '\t']).unwrap_or(rest);
+    Some((fields, text))
+}
+
+/// Writes `song` as an UltraStar `.txt` chart with its notes placed on the
+/// beat grid of `bpm`.
+///
+/// A song with two tracks becomes a duet. UltraStar has no room for more.
+pub fn write(song: &Song, bpm: f32, path: &Path) -> Result<(), FormatError> {
+    if !(bpm.is_finite() && bpm > 0.0) {
+        return Err(FormatError::parse(path, 0, "BPM must be positive"));
+    }
+    let mut tracks: Vec<&Track> = song.tracks.values().collect();
+    if tracks.len() > 2 {
+        return Err(FormatError::parse(
+            path,
+            0,
+            "UltraStar charts have at most two singers",
+        ));
+    }
+    tracks.sort_by(|a, b| a.name.cmp(&b.name));
+    fs::write(path, serialize(song, &tracks, bpm)).map_err(|e| FormatError::io(path, e))
+}
+
+/// Serializes `tracks` of `song` as an UltraStar chart. `#GAP` is the start
+/// of the earliest note, so the first note falls on beat 0.
+pub fn serialize(song: &Song, tracks: &[&Track], bpm: f32) -> String {
+    let beat_ms = 60000.0 / (bpm as f64 * TICKS_PER_BEAT as f64);
+    let gap = tracks
+        .iter()
+        .filter_map(|track| first_note_ms(track))
+        .fold(None, |min: Option<f64>, ms| {
+            Some(min.map_or(ms, |min| min.min(ms)))
+        })
+        .unwrap_or(0.0);
+
+    let mut s = String::new();
+    s += &format!("#TITLE:{}\n", song.name);
+    s += &format!("#ARTIST:{}\n", song.artist);
+    if let Some(name) = song.audio_path.as_ref().and_then(|p| p.file_name()) {
+        s += &format!("#MP3:{}\n", name.to_string_lossy());
+    }
+    if let Some(name) = song.video_path.as_ref().and_then(|p| p.file_name()) {
+        s += &format!("#VIDEO:{}\n", name.to_string_lossy());
+    }
+    s += &format!("#BPM:{}\n", bpm);
+    s += &format!("#GAP:{}\n", gap.round());
+
+    let duet = tracks.len() > 1;
+    if duet {
+        for (i, track) in tracks.iter().enumerate() {
+            s += &format!("#DUETSINGERP{}:{}\n", i + 1, track.name);
+        }
+    }
+    for (i, track) in tracks.iter().enumerate() {
+        if duet {
+            s += &format!("P{}\n", i + 1);
+        }
+        for line in note_lines(track, gap, beat_ms) {
+            s += &line;
+            s += "\n";
+        }
+    }
+    s += "E\n";
+    s
+}
+
+/// An unvoiced note without a lyric is a rest and takes no line.
+fn is_rest(note: &Note) -> bool {
+    !note.voiced && note.lyric.trim().is_empty()
+}
+
+fn first_note_ms(track: &Track) -> Option<f64> {
+    let mut position = 0;
+    for note in track.phrases.iter().flatten() {
+        if !is_rest(note) {
+            return Some(track.header.gap as f64 + track.header.position_to_ms(position));
+        }
+        position += note.length;
+    }
+    None
+}
+
+fn note_lines(track: &Track, gap: f64, beat_ms: f64) -> Vec<String> {
+    let beat = |position: u32| {
+        let ms = track.header.gap as f64 + track.header.position_to_ms(position);
+        ((ms - gap) / beat_ms).round() as i64
+    };
+    let mut lines = vec![];
+    let mut position = 0;
+    let mut line_break = None;
+    for phrase in &track.phrases {
+        let mut sung = false;
+        for note in phrase {
+            if !is_rest(note) {
+                if let Some(line_break) = line_break.take() {
+                    lines.push(format!("- {}", line_break));
+                }
+                let start = beat(position);
+                let end = beat(position + note.length);
+                // Unvoiced notes with lyrics have nothing to sing, so they
+                // aren't scored.
+                let kind = match (note.voiced, note.kind) {
+                    (false, _) | (true, NoteKind::Freestyle) => 'F',
+                    (true, NoteKind::Normal) => ':',
+                    (true, NoteKind::Golden) => '*',
+                    (true, NoteKind::Rap) => 'R',
+                };
+                let text = note.lyric.replace(['\n', '\r'], " ");
+                // Words are separated by a space before their first syllable.
+                let text = if text.is_empty() {
+                    "~".to_string()
+                } else if sung && !note.continues {
+                    format!(" {}", text)
+                } else {
+                    text
+                };
+                let line = |start: i64, end: i64, pitch: i8, text: &str| {
+                    format!(
+                        "{} {} {} {} {}",
+                        kind,
+                        start,
+                        (end - start).max(1),
+                        pitch as i32 - PITCH_OFFSET,
+                        text
+                    )
+                };
+                // UltraStar has no slides, so one is sung as its start pitch
+                // and then held at its end pitch.
+                match note.slide_to {
+                    Some(slide_to) if slide_to != note.pitch && end - start >= 2 => {
+                        let middle = start + (end - start) / 2;
+                        lines.push(line(start, middle, note.pitch, &text));
+                        lines.push(line(middle, end, slide_to, "~"));
+                    }
+                    _ => lines.push(line(start, end, note.pitch, &text)),
+                }
+                sung = true;
+            }
+            position += note.length;
+        }
+        if sung {
+            line_break = Some(beat(position));
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_chart(s: &str) -> Song {
+        parse(s, Path::new("song.txt")).unwrap()
+    }
+
+    fn lengths(track: &Track) -> Vec<Vec<u32>> {
+        track
+            .phrases
+            .iter()
+            .map(|phrase| phrase.iter().map(|note| note.length).collect())
+            .collect()
+    }
+
+    #[test]
+    fn tells_charts_from_other_text() {
+        assert!(is_chart("\u{feff}#TITLE:Song\n#bpm: 120\n: 0 1 0 la\n"));
+        assert!(!is_chart("#TITLE:Song\n"));
+        assert!(!is_chart("Read me first.\nBPM: 120\n"));
+    }
+
+    #[test]
+    fn reads_relative_beats() {
+        let song = parse_chart(
+            "#TITLE:Song\n#BPM:120\n#RELATIVE:yes\n: 0 2 0 A\n: 2 2 2 B\n- 4 6\n: 0 2 4 C\n- 4\n: 0 2 5 D\nE\n",
+        );
+        let track = &song.tracks["Song"];
+        // The second line starts at beat 6 and the third at 6 + 4.
+        assert_eq!(lengths(track), [vec![2, 2], vec![2, 2, 2], vec![2]]);
+        let sung: Vec<(i8, &str)> = track
+            .phrases
+            .iter()
+            .flatten()
+            .filter(|note| note.voiced)
+            .map(|note| (note.pitch, note.lyric.as_str()))
+            .collect();
+        assert_eq!(sung, [(60, "A"), (62, "B"), (64, "C"), (65, "D")]);
+    }
+
+    #[test]
+    fn reads_duets() {
+        let song = parse_chart(
+            "#TITLE:Duet\n#BPM:120\n#P1:Alice\n#P2:Bob\nP1\n: 0 4 0 la\nP2\n: 8 4 2 di\nE\n",
+        );
+        let mut names: Vec<&String> = song.tracks.keys().collect();
+        names.sort();
+        assert_eq!(names, ["Alice", "Bob"]);
+        assert_eq!(song.tracks["Alice"].header.gap, 0);
+        // A beat is a quarter of 500 ms at 120 BPM.
+        assert_eq!(song.tracks["Bob"].header.gap, 1000);
+        assert_eq!(song.tracks["Bob"].phrases[0][0].pitch, 62);
+    }
+
+    #[test]
+    fn holds_syllables_with_tilde() {
+        let song =
+            parse_chart("#TITLE:Song\n#BPM:120\n: 0 2 0 Hel\n: 2 2 2 ~\n: 4 2 0  world\nE\n");
+        let notes: Vec<(&str, bool)> = song.tracks["Song"].phrases[0]
+            .iter()
+            .map(|note| (note.lyric.as_str(), note.continues))
+            .collect();
+        assert_eq!(notes, [("Hel", false), ("", true), ("world", false)]);
+    }
+
+    #[test]
+    fn round_trips_charts() {
+        let chart = "#TITLE:Song\n#ARTIST:Band\n#BPM:300\n#GAP:1500\n\
+            : 0 4 0 Hel\n: 4 4 2 lo\n* 8 4 4  world\n- 14\n\
+            F 16 4 0 spo\nR 20 4 5 ken\n: 24 2 7 ~\nE\n";
+        let song = parse_chart(chart);
+        let track = &song.tracks["Song"];
+        assert_eq!(track.header.bpm, Some(300.0));
+        assert_eq!(track.header.gap, 1500);
+        let kinds: Vec<NoteKind> = track
+            .phrases
+            .iter()
+            .flatten()
+            .filter(|note| note.voiced)
+            .map(|note| note.kind)
+            .collect();
+        use NoteKind::*;
+        assert_eq!(kinds, [Normal, Normal, Golden, Freestyle, Rap, Normal]);
+
+        let written = serialize(&song, &[track], 300.0);
+        assert_eq!(written, chart);
+        let again = parse_chart(&written);
+        assert_eq!(again.tracks["Song"].phrases, track.phrases);
+        assert_eq!(again.tracks["Song"].header, track.header);
+    }
+
+    #[test]
+    fn writes_slides_as_held_notes() {
+        let mut track = Track::new();
+        track.name = "Song".to_string();
+        track.header = TrackHeader {
+            bpm: Some(300.0),
+            resolution: Resolution::Ticks {
+                ticks_per_beat: TICKS_PER_BEAT,
+            },
+            ..TrackHeader::new()
+        };
+        track.phrases = vec![vec![
+            Note::new(4, 60, true, "la".to_string()).with_slide(Some(64))
+        ]];
+        let song = Song::new("Song".to_string(), String::new(), String::new(), None, None);
+        let written = serialize(&song, &[&track], 300.0);
+        assert!(written.contains(": 0 2 0 la\n: 2 2 4 ~\n"));
+        let read = parse_chart(&written);
+        let notes: Vec<(i8, &str, bool)> = read.tracks["Song"].phrases[0]
+            .iter()
+            .map(|note| (note.pitch, note.lyric.as_str(), note.continues))
+            .collect();
+        assert_eq!(notes, [(60, "la", false), (64, "", true)]);
+        assert_eq!(serialize(&read, &[&read.tracks["Song"]], 300.0), written);
+    }
+
+    #[test]
+    fn reads_decimal_commas() {
+        let song = parse_chart("#TITLE:Song\n#BPM:150,5\n#GAP:1000,4\n: 0 2 0 la\nE\n");
+        let header = &song.tracks["Song"].header;
+        assert_eq!(header.bpm, Some(150.5));
+        assert_eq!(header.gap, 1000);
+    }
+}